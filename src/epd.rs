@@ -0,0 +1,467 @@
+// Running EPD test suites, like WAC or STS. Every position is searched with a fixed budget, and counts as solved
+// when the search ends on one of the best moves (bm), on none of the moves to avoid (am), and with a mate at
+// least as short as asked for (dm). STS style comments scoring several moves, like c0 "Qd2=10, Qe1=5", give
+// points for any of them.
+//
+// usage: ShenYu epd <suite> [movetime <ms> | depth <plies> | nodes <nodes>] [threads]
+// The search gets one second per position by default.
+
+use std::{
+    fs,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+
+use shenyu::{
+    movegen::{action::Action, board::Board},
+    search::{
+        searchcontrol::{Iteration, Searcher},
+        timer::SearchLimits,
+    },
+};
+
+const DEFAULT_MOVETIME: u64 = 1000;
+const STS_MAX_POINTS: u32 = 10;
+
+#[derive(Clone, Debug, Default)]
+pub struct Epd {
+    pub fen: String,
+    pub id: Option<String>,
+    pub best_moves: Vec<Action>,
+    pub avoid_moves: Vec<Action>,
+    pub comment: Option<String>,    // c0
+    pub direct_mate: Option<i16>,   // moves
+    pub points: Vec<(Action, u32)>, // from an sts style comment
+}
+
+pub fn run(mut args: impl Iterator<Item = String>) -> Result<()> {
+    const USAGE: &str =
+        "usage: epd <suite> [movetime <ms> | depth <plies> | nodes <nodes>] [threads]";
+    let input = args.next().context(USAGE)?;
+    let mut limits = SearchLimits::default();
+    match args.next().as_deref() {
+        None => limits.movetime = Some(DEFAULT_MOVETIME),
+        Some(kind) => {
+            let amount = args.next().context(USAGE)?;
+            match kind {
+                "movetime" => limits.movetime = Some(amount.parse().context("Invalid movetime")?),
+                "depth" => limits.depth = Some(amount.parse().context("Invalid depth")?),
+                "nodes" => limits.nodes = Some(amount.parse().context("Invalid node count")?),
+                _ => bail!(USAGE),
+            }
+        }
+    }
+    let threads = match args.next() {
+        Some(threads) => threads.parse().context("Invalid thread count")?,
+        None => 1,
+    };
+
+    let text = fs::read_to_string(&input).with_context(|| format!("Could not open {input}"))?;
+    let mut suite = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let epd = Epd::parse(line).with_context(|| format!("{input}, line {}", number + 1))?;
+        suite.push(epd);
+    }
+
+    // the search doesn't take commands from anywhere, it just runs out of budget
+    let mut searcher = Searcher::default();
+    searcher.set_threads(threads);
+
+    let mut solved = 0;
+    let mut points = 0;
+    let mut max_points = 0;
+    let mut solve_time = Duration::ZERO;
+    let start = Instant::now();
+    for (number, epd) in suite.iter().enumerate() {
+        searcher.reset();
+        searcher.get_board().parse_fen(&epd.fen)?;
+        let result = searcher.search(&limits);
+        let best_move = result.best_move.unwrap_or_default();
+        let san = match result.best_move {
+            Some(best_move) => searcher.get_board().to_san(best_move),
+            None => String::from("none"),
+        };
+        let iterations = &result.iterations;
+
+        let name = epd.id.clone().unwrap_or_else(|| format!("#{}", number + 1));
+        let solution = epd.time_to_solution(iterations);
+        if let Some(time) = solution {
+            solved += 1;
+            solve_time += time;
+        }
+        let earned = epd.points(best_move, solution.is_some());
+        points += earned;
+        max_points += if epd.points.is_empty() {
+            1
+        } else {
+            STS_MAX_POINTS
+        };
+
+        let depth = iterations.last().map_or(0, |iteration| iteration.depth);
+        let result = match solution {
+            Some(time) => format!("solved in {:.2}s", time.as_secs_f64()),
+            None => String::from("failed"),
+        };
+        println!(
+            "{:>4} {name:<20} {san:<8} depth {depth:<3} {result:<18} expected {}",
+            number + 1,
+            epd.expected(searcher.get_board())
+        );
+    }
+
+    let total = suite.len();
+    println!();
+    println!(
+        "solved {solved}/{total} ({:.1}%) in {:.1}s",
+        solved as f64 * 100.0 / total.max(1) as f64,
+        start.elapsed().as_secs_f64()
+    );
+    if solved > 0 {
+        println!(
+            "average time to solution {:.2}s",
+            solve_time.as_secs_f64() / solved as f64
+        );
+    }
+    println!(
+        "score {points}/{max_points} ({:.1}%)",
+        points as f64 * 100.0 / max_points.max(1) as f64
+    );
+    Ok(())
+}
+
+impl Epd {
+    // The four position fields, then operations separated by semicolons. The move counters can be given with
+    // the hmvc and fmvn operations.
+    pub fn parse(line: &str) -> Result<Epd> {
+        let mut rest = line.trim();
+        let mut position = Vec::new();
+        for _ in 0..4 {
+            let (field, remainder) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            if field.is_empty() {
+                bail!("Invalid epd")
+            }
+            position.push(field);
+            rest = remainder.trim_start();
+        }
+        let operations = parse_operations(rest)?;
+
+        let counter = |opcode: &str, default: &str| {
+            operations
+                .iter()
+                .find(|(name, _)| name == opcode)
+                .and_then(|(_, operands)| operands.first().cloned())
+                .unwrap_or_else(|| default.to_string())
+        };
+        let mut epd = Epd {
+            fen: format!(
+                "{} {} {}",
+                position.join(" "),
+                counter("hmvc", "0"),
+                counter("fmvn", "1")
+            ),
+            ..Epd::default()
+        };
+        let mut board = Board::new();
+        board.parse_fen(&epd.fen)?;
+
+        for (opcode, operands) in operations {
+            match opcode.as_str() {
+                "bm" => epd.best_moves = parse_moves(&mut board, &operands)?,
+                "am" => epd.avoid_moves = parse_moves(&mut board, &operands)?,
+                "dm" => {
+                    let operand = operands.first().context("Missing mate length")?;
+                    epd.direct_mate = Some(operand.parse().context("Invalid mate length")?);
+                }
+                "id" => epd.id = operands.first().cloned(),
+                "c0" => {
+                    let comment = operands.join(" ");
+                    epd.points = parse_points(&mut board, &comment).unwrap_or_default();
+                    epd.comment = Some(comment);
+                }
+                // anything else is left alone
+                _ => (),
+            }
+        }
+        Ok(epd)
+    }
+
+    // How long the search took to settle on a solution it then kept until the end, or None if it ended on
+    // something else. Positions with nothing to solve are never solved.
+    pub fn time_to_solution(&self, iterations: &[Iteration]) -> Option<Duration> {
+        if self.best_moves.is_empty() && self.avoid_moves.is_empty() && self.direct_mate.is_none() {
+            return None;
+        }
+        let first_solved = iterations
+            .iter()
+            .rposition(|iteration| !self.is_solution(iteration))
+            .map_or(0, |unsolved| unsolved + 1);
+        iterations
+            .get(first_solved)
+            .map(|iteration| Duration::from_millis(iteration.time))
+    }
+
+    fn is_solution(&self, iteration: &Iteration) -> bool {
+        let best_move = iteration.best_move;
+        (self.best_moves.is_empty() || self.best_moves.contains(&best_move))
+            && !self.avoid_moves.contains(&best_move)
+            && self
+                .direct_mate
+                .is_none_or(|moves| iteration.mate.is_some_and(|mate| mate > 0 && mate <= moves))
+    }
+
+    // sts points if the suite gives them, otherwise one for a solve
+    fn points(&self, best_move: Action, solved: bool) -> u32 {
+        if self.points.is_empty() {
+            return solved as u32;
+        }
+        self.points
+            .iter()
+            .find(|&&(action, _)| action == best_move)
+            .map_or(0, |&(_, points)| points)
+    }
+
+    fn expected(&self, board: &Board) -> String {
+        let mut expected = Vec::new();
+        if !self.best_moves.is_empty() {
+            let moves = self.best_moves.iter().map(|&action| board.to_san(action));
+            expected.push(format!("bm {}", moves.collect::<Vec<_>>().join(" ")));
+        }
+        if !self.avoid_moves.is_empty() {
+            let moves = self.avoid_moves.iter().map(|&action| board.to_san(action));
+            expected.push(format!("am {}", moves.collect::<Vec<_>>().join(" ")));
+        }
+        if let Some(moves) = self.direct_mate {
+            expected.push(format!("dm {moves}"));
+        }
+        expected.join(", ")
+    }
+}
+
+// opcodes with their operands. Quoted operands can hold spaces and semicolons.
+fn parse_operations(text: &str) -> Result<Vec<(String, Vec<String>)>> {
+    let mut operations = Vec::new();
+    let mut words = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(character) = chars.next() {
+        match character {
+            ';' => {
+                if !words.is_empty() {
+                    let opcode = words.remove(0);
+                    operations.push((opcode, words));
+                    words = Vec::new();
+                }
+            }
+            '"' => {
+                let mut quoted = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(character) => quoted.push(character),
+                        None => bail!("Unterminated quote"),
+                    }
+                }
+                words.push(quoted);
+            }
+            character if character.is_whitespace() => (),
+            _ => {
+                let mut word = String::from(character);
+                while let Some(character) =
+                    chars.next_if(|&character| !character.is_whitespace() && character != ';')
+                {
+                    word.push(character);
+                }
+                words.push(word);
+            }
+        }
+    }
+    // the last operation is often left unterminated
+    if !words.is_empty() {
+        let opcode = words.remove(0);
+        operations.push((opcode, words));
+    }
+    Ok(operations)
+}
+
+fn parse_moves(board: &mut Board, operands: &[String]) -> Result<Vec<Action>> {
+    operands
+        .iter()
+        .map(|san| {
+            board
+                .parse_san(san)
+                .with_context(|| format!("Illegal move {san}"))
+        })
+        .collect()
+}
+
+// "Nd5=10, Qc2=5", or None if the comment isn't in that form
+fn parse_points(board: &mut Board, comment: &str) -> Option<Vec<(Action, u32)>> {
+    comment
+        .split(',')
+        .map(|entry| {
+            let (san, points) = entry.trim().rsplit_once('=')?;
+            Some((board.parse_san(san)?, points.parse().ok()?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STARTPOS: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq -";
+
+    fn epd(operations: &str) -> Epd {
+        Epd::parse(&format!("{STARTPOS} {operations}")).unwrap()
+    }
+
+    fn action(movestring: &str) -> Action {
+        let mut board = Board::new();
+        board.parse_fen(&format!("{STARTPOS} 0 1")).unwrap();
+        board.parse_san(movestring).unwrap()
+    }
+
+    // an iteration ending on the move after the given number of ms
+    fn iteration(movestring: &str, mate: Option<i16>, time: u64) -> Iteration {
+        Iteration {
+            depth: 1,
+            score: 0,
+            mate,
+            best_move: action(movestring),
+            nodes: 0,
+            time,
+        }
+    }
+
+    #[test]
+    fn operations() {
+        let operations = |text| parse_operations(text).unwrap();
+        let operation = |opcode: &str, operands: &[&str]| {
+            let operands = operands.iter().map(|operand| operand.to_string()).collect();
+            (opcode.to_string(), operands)
+        };
+        assert_eq!(
+            operations("bm Nf3 e4; id \"WAC.001; part two\";c0 \"a  b\" c;"),
+            [
+                operation("bm", &["Nf3", "e4"]),
+                operation("id", &["WAC.001; part two"]),
+                operation("c0", &["a  b", "c"]),
+            ]
+        );
+        // the last one doesn't need a semicolon, and empty operations are skipped
+        assert_eq!(
+            operations("noop; ; dm 3"),
+            [operation("noop", &[]), operation("dm", &["3"])]
+        );
+        assert_eq!(operations(""), []);
+        assert!(parse_operations("id \"WAC.001; bm e4;").is_err());
+        assert!(Epd::parse(&format!("{STARTPOS} bm e4; id \"unterminated")).is_err());
+    }
+
+    #[test]
+    fn parse() {
+        let epd = epd("bm e4 d4; am f3; id \"test; one\"; dm 4;");
+        assert_eq!(epd.fen, format!("{STARTPOS} 0 1"));
+        assert_eq!(epd.best_moves, [action("e4"), action("d4")]);
+        assert_eq!(epd.avoid_moves, [action("f3")]);
+        assert_eq!(epd.id.as_deref(), Some("test; one"));
+        assert_eq!(epd.direct_mate, Some(4));
+
+        assert!(Epd::parse(&format!("{STARTPOS} bm e5;")).is_err());
+        assert!(Epd::parse(&format!("{STARTPOS} dm x;")).is_err());
+        assert!(Epd::parse("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq").is_err());
+    }
+
+    #[test]
+    fn move_counters() {
+        assert_eq!(epd("hmvc 12; fmvn 40;").fen, format!("{STARTPOS} 12 40"));
+        assert_eq!(epd("fmvn 40").fen, format!("{STARTPOS} 0 40"));
+        assert!(Epd::parse(&format!("{STARTPOS} hmvc x;")).is_err());
+    }
+
+    #[test]
+    fn sts_points() {
+        let sts = epd("bm e4; c0 \"e4=10, d4=9, Nf3=7\";");
+        assert_eq!(
+            sts.points,
+            [(action("e4"), 10), (action("d4"), 9), (action("Nf3"), 7)]
+        );
+        assert_eq!(sts.points(action("d4"), false), 9);
+        assert_eq!(sts.points(action("c4"), false), 0);
+
+        // any other comment is just a comment, and a solve is worth a point
+        let plain = epd("bm e4; c0 \"e4 is best by test\";");
+        assert_eq!(plain.comment.as_deref(), Some("e4 is best by test"));
+        assert!(plain.points.is_empty());
+        assert_eq!(plain.points(action("e4"), true), 1);
+        assert_eq!(plain.points(action("d4"), false), 0);
+        assert!(epd("c0 \"e4=10, e5=5\"").points.is_empty());
+    }
+
+    #[test]
+    fn best_moves() {
+        let epd = epd("bm e4 d4;");
+        let solved = |iterations: &[Iteration]| epd.time_to_solution(iterations);
+        assert_eq!(solved(&[]), None);
+        assert_eq!(
+            solved(&[iteration("e4", None, 10), iteration("d4", None, 20)]),
+            Some(Duration::from_millis(10))
+        );
+        // it has to be kept until the end
+        assert_eq!(
+            solved(&[
+                iteration("e4", None, 10),
+                iteration("c4", None, 20),
+                iteration("e4", None, 30),
+                iteration("e4", None, 40),
+            ]),
+            Some(Duration::from_millis(30))
+        );
+        assert_eq!(
+            solved(&[iteration("e4", None, 10), iteration("c4", None, 20)]),
+            None
+        );
+    }
+
+    #[test]
+    fn avoid_moves() {
+        let epd = epd("am f3 g4;");
+        let solved = |iterations: &[Iteration]| epd.time_to_solution(iterations);
+        assert_eq!(
+            solved(&[iteration("g4", None, 10), iteration("e4", None, 20)]),
+            Some(Duration::from_millis(20))
+        );
+        assert_eq!(
+            solved(&[iteration("e4", None, 10), iteration("f3", None, 20)]),
+            None
+        );
+    }
+
+    #[test]
+    fn direct_mate() {
+        let epd = epd("bm e4; dm 2;");
+        let solved = |iterations: &[Iteration]| epd.time_to_solution(iterations);
+        // the mate can be shorter than asked for, but has to be on a best move
+        assert_eq!(
+            solved(&[iteration("e4", Some(3), 10), iteration("e4", Some(2), 20)]),
+            Some(Duration::from_millis(20))
+        );
+        assert_eq!(
+            solved(&[iteration("e4", Some(1), 10)]),
+            Some(Duration::from_millis(10))
+        );
+        assert_eq!(solved(&[iteration("d4", Some(1), 10)]), None);
+        assert_eq!(solved(&[iteration("e4", Some(3), 10)]), None);
+        assert_eq!(solved(&[iteration("e4", Some(-2), 10)]), None);
+        assert_eq!(solved(&[iteration("e4", None, 10)]), None);
+
+        // nothing to solve
+        assert_eq!(
+            self::epd("id \"nothing\";").time_to_solution(&[iteration("e4", None, 10)]),
+            None
+        );
+    }
+}
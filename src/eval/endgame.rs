@@ -0,0 +1,505 @@
+// Endgame knowledge the general evaluation gets wrong. Endgames with a known outcome are recognized by
+// their material signature and given an exact score, and a few drawish endgames scale the endgame half
+// of the evaluation down.
+
+use static_init::dynamic;
+
+use crate::movegen::{
+    bitboard::{self, Bitboard},
+    board::Board,
+    types::{
+        square::{self, A1, A8, H1, H8},
+        Color, Piece, Square,
+    },
+};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Endgame {
+    Exact(i16),  // from white's point of view
+    Scaled(i16), // out of SCALE_NORMAL
+}
+
+pub const SCALE_NORMAL: i16 = 64;
+const SCALE_OPPOSITE_BISHOPS: i16 = 22; // nothing but bishops and pawns
+const SCALE_OPPOSITE_BISHOPS_WITH_PIECES: i16 = 46;
+
+// well above anything the evaluation gives otherwise, and well below mate scores
+const KNOWN_WIN: i16 = 2000;
+const ROOK_VALUE: i16 = 500;
+
+const DARK_SQUARES: Bitboard = 0xaa55_aa55_aa55_aa55;
+const PIECES: [Piece; 5] = [Piece::P, Piece::N, Piece::B, Piece::R, Piece::Q];
+
+// scores a position from the strong side's point of view
+type Evaluator = fn(&Board, Color) -> i16;
+
+// signature, strong side and evaluator
+#[dynamic]
+static ENDGAMES: Vec<(u64, Color, Evaluator)> = {
+    let endgames: [(&str, Evaluator); 3] = [("KPK", kpk), ("KBNK", kbnk), ("KRKP", krkp)];
+    let mut table = Vec::new();
+    for (code, evaluator) in endgames {
+        for strong in [Color::W, Color::B] {
+            table.push((signature(code, strong), strong, evaluator as Evaluator));
+        }
+    }
+    table
+};
+
+impl Board {
+    // how many of each piece both sides have, 4 bits per piece with white's in the high half
+    pub fn material_signature(&self) -> u64 {
+        let mut signature = 0;
+        for color in [Color::W, Color::B] {
+            for piece in PIECES {
+                signature =
+                    signature << 4 | self.piece_bb(piece, color).count_ones().min(15) as u64;
+            }
+        }
+        signature
+    }
+
+    // the signature of any position with these pieces, each side written like "KRP"
+    pub fn signature_of(white: &str, black: &str) -> u64 {
+        let mut signature = 0;
+        for pieces in [white, black] {
+            for piece in ['P', 'N', 'B', 'R', 'Q'] {
+                signature = signature << 4 | pieces.matches(piece).count().min(15) as u64;
+            }
+        }
+        signature
+    }
+
+    pub fn endgame(&self) -> Option<Endgame> {
+        // every endgame with an exact score has at most four pieces
+        if self.occupancy().count_ones() <= 4 {
+            let signature = self.material_signature();
+            if let Some((_, strong, evaluator)) = ENDGAMES
+                .iter()
+                .find(|(endgame, _, _)| *endgame == signature)
+            {
+                let eval = evaluator(self, *strong);
+                return Some(Endgame::Exact(match strong {
+                    Color::W => eval,
+                    Color::B => -eval,
+                }));
+            }
+        }
+        scale_factor(self).map(Endgame::Scaled)
+    }
+}
+
+// a code like "KRKP" lists the strong side's pieces and then the weak side's
+fn signature(code: &str, strong: Color) -> u64 {
+    let (strong_pieces, weak_pieces) = code.split_at(code.rfind('K').unwrap());
+    match strong {
+        Color::W => Board::signature_of(strong_pieces, weak_pieces),
+        Color::B => Board::signature_of(weak_pieces, strong_pieces),
+    }
+}
+
+fn distance(square1: Square, square2: Square) -> i16 {
+    let ranks = square::rank_of(square1).abs_diff(square::rank_of(square2));
+    let files = square::file_of(square1).abs_diff(square::file_of(square2));
+    ranks.max(files) as i16
+}
+
+fn king_square(board: &Board, color: Color) -> Square {
+    board.piece_bb(Piece::K, color).trailing_zeros() as Square
+}
+
+fn piece_square(board: &Board, piece: Piece, color: Color) -> Square {
+    board.piece_bb(piece, color).trailing_zeros() as Square
+}
+
+// squares seen from the side's point of view, so that it always plays up the board
+fn relative(square: Square, color: Color) -> Square {
+    match color {
+        Color::W => square,
+        Color::B => square::flip_v(square),
+    }
+}
+
+fn kpk(board: &Board, strong: Color) -> i16 {
+    let mut strong_king = relative(king_square(board, strong), strong);
+    let mut weak_king = relative(king_square(board, !strong), strong);
+    let mut pawn = relative(piece_square(board, Piece::P, strong), strong);
+    // the bitbase only holds pawns on the queenside
+    if square::file_of(pawn) >= 4 {
+        strong_king = square::flip_h(strong_king);
+        weak_king = square::flip_h(weak_king);
+        pawn = square::flip_h(pawn);
+    }
+    let strong_to_move = board.active_color() == strong;
+    if kpk::probe(strong_king, weak_king, pawn, strong_to_move) {
+        KNOWN_WIN + 10 * square::rank_of(pawn) as i16
+    } else {
+        0
+    }
+}
+
+// The weak king has to be driven into a corner the bishop covers, with the strong king close by.
+fn kbnk(board: &Board, strong: Color) -> i16 {
+    let strong_king = king_square(board, strong);
+    let weak_king = king_square(board, !strong);
+    let corners = if board.piece_bb(Piece::B, strong) & DARK_SQUARES != 0 {
+        [A1, H8]
+    } else {
+        [H1, A8]
+    };
+    let corner_distance = corners
+        .map(|corner| {
+            let ranks = square::rank_of(weak_king).abs_diff(square::rank_of(corner));
+            let files = square::file_of(weak_king).abs_diff(square::file_of(corner));
+            (ranks + files) as i16
+        })
+        .into_iter()
+        .min()
+        .unwrap();
+    KNOWN_WIN + 40 * (14 - corner_distance) + 10 * (7 - distance(strong_king, weak_king))
+}
+
+// Usually a win for the rook, unless the pawn is far up the board and well supported.
+fn krkp(board: &Board, strong: Color) -> i16 {
+    let strong_king = relative(king_square(board, strong), strong);
+    let weak_king = relative(king_square(board, !strong), strong);
+    let rook = relative(piece_square(board, Piece::R, strong), strong);
+    let pawn = relative(piece_square(board, Piece::P, !strong), strong);
+    let queening = square::new_sq(0, square::file_of(pawn));
+    let in_front = pawn - 8;
+    let weak_to_move = (board.active_color() != strong) as i16;
+
+    if square::file_of(strong_king) == square::file_of(pawn) && strong_king < pawn {
+        // the strong king blocks the pawn
+        ROOK_VALUE - distance(strong_king, pawn)
+    } else if distance(weak_king, pawn) >= 3 + weak_to_move && distance(weak_king, rook) >= 3 {
+        // the rook picks off the pawn before the weak king can support it
+        ROOK_VALUE - distance(strong_king, pawn)
+    } else if square::rank_of(weak_king) <= 2
+        && distance(weak_king, pawn) == 1
+        && square::rank_of(strong_king) >= 3
+        && distance(strong_king, pawn) > 3 - weak_to_move
+    {
+        80 - 8 * distance(strong_king, pawn)
+    } else {
+        200 - 8
+            * (distance(strong_king, in_front)
+                - distance(weak_king, in_front)
+                - distance(pawn, queening))
+    }
+}
+
+fn scale_factor(board: &Board) -> Option<i16> {
+    let white_bishops = board.piece_bb(Piece::B, Color::W);
+    let black_bishops = board.piece_bb(Piece::B, Color::B);
+    let other_pieces = board.pieces()[Piece::N as usize]
+        | board.pieces()[Piece::R as usize]
+        | board.pieces()[Piece::Q as usize];
+
+    if white_bishops.count_ones() == 1
+        && black_bishops.count_ones() == 1
+        && (white_bishops & DARK_SQUARES == 0) != (black_bishops & DARK_SQUARES == 0)
+    {
+        return Some(if other_pieces == 0 {
+            SCALE_OPPOSITE_BISHOPS
+        } else {
+            SCALE_OPPOSITE_BISHOPS_WITH_PIECES
+        });
+    }
+
+    for strong in [Color::W, Color::B] {
+        if wrong_rook_pawn(board, strong) {
+            return Some(0);
+        }
+    }
+    None
+}
+
+// Bishop and rook pawns against a bare king is a draw when the bishop can't cover the queening square
+// and the king gets there first.
+fn wrong_rook_pawn(board: &Board, strong: Color) -> bool {
+    let pawns = board.piece_bb(Piece::P, strong);
+    let bishops = board.piece_bb(Piece::B, strong);
+    let non_pawns = board.color_bb(strong) & !pawns & !board.piece_bb(Piece::K, strong);
+    if pawns == 0
+        || bishops == 0
+        || non_pawns != bishops
+        || board.color_bb(!strong).count_ones() != 1
+    {
+        return false;
+    }
+
+    let file = [bitboard::file_bb_of(A1), bitboard::file_bb_of(H1)]
+        .into_iter()
+        .find(|&file| pawns & !file == 0);
+    let Some(file) = file else {
+        return false;
+    };
+    let queening = relative(
+        square::new_sq(7, square::file_of(file.trailing_zeros() as Square)),
+        strong,
+    );
+    let queening_dark = bitboard::new_bb(queening) & DARK_SQUARES != 0;
+    let bishop_covers = if queening_dark {
+        bishops & DARK_SQUARES != 0
+    } else {
+        bishops & !DARK_SQUARES != 0
+    };
+    !bishop_covers && distance(king_square(board, !strong), queening) <= 1
+}
+
+// A bitbase of king and pawn against king. Built once at startup by retrograde analysis: positions that
+// are won or drawn outright are marked first, and the rest are classified from their successors until
+// nothing changes. Positions are from white's point of view, with the pawn on the a to d files.
+mod kpk {
+    use static_init::dynamic;
+
+    use crate::movegen::{
+        atks,
+        bitboard::{self, Bitboard, Direction},
+        types::{square, Square},
+    };
+
+    use super::distance;
+
+    // side to move, two kings and a pawn on one of 24 squares
+    const MAX_INDEX: usize = 2 * 24 * 64 * 64;
+
+    const INVALID: u8 = 0;
+    const UNKNOWN: u8 = 1;
+    const DRAW: u8 = 2;
+    const WIN: u8 = 4;
+
+    #[dynamic]
+    static BITBASE: Vec<u64> = generate();
+
+    pub(super) fn probe(
+        white_king: Square,
+        black_king: Square,
+        pawn: Square,
+        white_to_move: bool,
+    ) -> bool {
+        let index = index(white_to_move, white_king, black_king, pawn);
+        BITBASE[index / 64] >> (index % 64) & 1 != 0
+    }
+
+    fn index(white_to_move: bool, white_king: Square, black_king: Square, pawn: Square) -> usize {
+        white_king as usize
+            | (black_king as usize) << 6
+            | (!white_to_move as usize) << 12
+            | (square::file_of(pawn) as usize) << 13
+            | (6 - square::rank_of(pawn) as usize) << 15
+    }
+
+    fn decode(index: usize) -> (bool, Square, Square, Square) {
+        let white_king = (index & 63) as Square;
+        let black_king = (index >> 6 & 63) as Square;
+        let white_to_move = index >> 12 & 1 == 0;
+        let file = (index >> 13 & 3) as u8;
+        let rank = 6 - (index >> 15) as u8;
+        (
+            white_to_move,
+            white_king,
+            black_king,
+            square::new_sq(rank, file),
+        )
+    }
+
+    fn pawn_attacks(pawn: Square) -> Bitboard {
+        let pawn = bitboard::new_bb(pawn);
+        bitboard::shift(pawn, Direction::NE) | bitboard::shift(pawn, Direction::NW)
+    }
+
+    fn generate() -> Vec<u64> {
+        let mut results = (0..MAX_INDEX).map(initial).collect::<Vec<u8>>();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for index in 0..MAX_INDEX {
+                if results[index] == UNKNOWN {
+                    let result = classify(&results, index);
+                    if result != UNKNOWN {
+                        results[index] = result;
+                        changed = true;
+                    }
+                }
+            }
+        }
+
+        let mut bitbase = vec![0; MAX_INDEX / 64];
+        for (index, &result) in results.iter().enumerate() {
+            if result == WIN {
+                bitbase[index / 64] |= 1 << (index % 64);
+            }
+        }
+        bitbase
+    }
+
+    fn initial(index: usize) -> u8 {
+        let (white_to_move, white_king, black_king, pawn) = decode(index);
+        let black_king_bb = bitboard::new_bb(black_king);
+        let push = pawn + 8;
+
+        if distance(white_king, black_king) <= 1
+            || white_king == pawn
+            || black_king == pawn
+            || (white_to_move && pawn_attacks(pawn) & black_king_bb != 0)
+        {
+            INVALID
+        } else if white_to_move
+            && square::rank_of(pawn) == 6
+            && white_king != push
+            && black_king != push
+            && (distance(black_king, push) > 1 || distance(white_king, push) == 1)
+        {
+            // promotes without the queen being taken
+            WIN
+        } else if !white_to_move
+            && (atks::king_attacks(black_king)
+                & !(atks::king_attacks(white_king) | pawn_attacks(pawn))
+                == 0
+                || atks::king_attacks(black_king)
+                    & !atks::king_attacks(white_king)
+                    & bitboard::new_bb(pawn)
+                    != 0)
+        {
+            // stalemate, or the pawn can be taken
+            DRAW
+        } else {
+            UNKNOWN
+        }
+    }
+
+    // white wins if any move wins, black draws if any move draws
+    fn classify(results: &[u8], position: usize) -> u8 {
+        let (white_to_move, white_king, black_king, pawn) = decode(position);
+        let (good, bad) = if white_to_move {
+            (WIN, DRAW)
+        } else {
+            (DRAW, WIN)
+        };
+
+        let mut result = INVALID;
+        let mut moves = atks::king_attacks(if white_to_move {
+            white_king
+        } else {
+            black_king
+        });
+        while moves > 0 {
+            let to = bitboard::pop_lsb(&mut moves);
+            result |= if white_to_move {
+                results[index(false, to, black_king, pawn)]
+            } else {
+                results[index(true, white_king, to, pawn)]
+            };
+        }
+        if white_to_move {
+            if square::rank_of(pawn) < 6 {
+                result |= results[index(false, white_king, black_king, pawn + 8)];
+            }
+            if square::rank_of(pawn) == 1 && pawn + 8 != white_king && pawn + 8 != black_king {
+                result |= results[index(false, white_king, black_king, pawn + 16)];
+            }
+        }
+
+        if result & good != 0 {
+            good
+        } else if result & UNKNOWN != 0 {
+            UNKNOWN
+        } else {
+            bad
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Endgame, KNOWN_WIN, SCALE_OPPOSITE_BISHOPS, SCALE_OPPOSITE_BISHOPS_WITH_PIECES};
+    use crate::movegen::board::Board;
+
+    fn endgame(fen: &str) -> Option<Endgame> {
+        let mut board = Board::new();
+        board.parse_fen(fen).unwrap();
+        board.endgame()
+    }
+
+    // white's point of view
+    fn exact(fen: &str) -> i16 {
+        match endgame(fen) {
+            Some(Endgame::Exact(eval)) => eval,
+            other => panic!("{fen} gave {other:?}"),
+        }
+    }
+
+    #[test]
+    fn kpk() {
+        // the king in front of its pawn on the sixth rank wins whoever is to move
+        assert!(exact("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1") >= KNOWN_WIN);
+        assert!(exact("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1") >= KNOWN_WIN);
+        // whoever has the opposition decides it
+        assert!(exact("8/8/4k3/8/4K3/4P3/8/8 b - - 0 1") >= KNOWN_WIN);
+        assert_eq!(exact("8/8/4k3/8/4K3/4P3/8/8 w - - 0 1"), 0);
+        // a rook pawn can't drive the king out of the corner
+        assert_eq!(exact("k7/8/K7/P7/8/8/8/8 w - - 0 1"), 0);
+        assert_eq!(exact("k7/8/K7/P7/8/8/8/8 b - - 0 1"), 0);
+        // the same cases with black as the strong side, and on the kingside
+        assert!(exact("8/8/4p3/4k3/8/4K3/8/8 w - - 0 1") <= -KNOWN_WIN);
+        assert_eq!(exact("8/8/4p3/4k3/8/4K3/8/8 b - - 0 1"), 0);
+        assert_eq!(exact("8/8/8/8/7p/7k/8/7K w - - 0 1"), 0);
+    }
+
+    #[test]
+    fn kbnk() {
+        // a dark squared bishop mates in a1 or h8
+        let right_corner = exact("8/8/8/4B3/3K4/5N2/8/k7 w - - 0 1");
+        let wrong_corner = exact("k7/8/8/4B3/3K4/5N2/8/8 w - - 0 1");
+        assert!(wrong_corner >= KNOWN_WIN);
+        assert!(right_corner > wrong_corner);
+        // and a light squared one in a8 or h1
+        let right_corner = exact("k7/8/8/8/3KB3/5N2/8/8 w - - 0 1");
+        let wrong_corner = exact("8/8/8/8/3KB3/5N2/8/k7 w - - 0 1");
+        assert!(right_corner > wrong_corner);
+        // the same position with the colors swapped, which puts the bishop on the other color too
+        assert_eq!(exact("8/8/5n2/3kb3/8/8/8/K7 b - - 0 1"), -right_corner);
+    }
+
+    #[test]
+    fn krkp() {
+        // the rook wins the pawn before the kings get involved
+        assert!(exact("8/8/8/8/1k6/8/6p1/R5K1 w - - 0 1") > 400);
+        // a pawn on the seventh with its king next to it is much harder
+        assert!(exact("8/8/8/8/8/K7/1kp5/7R w - - 0 1") < 300);
+    }
+
+    #[test]
+    fn opposite_colored_bishops() {
+        assert_eq!(
+            endgame("8/5k2/3b4/p7/P7/3B4/5K2/8 w - - 0 1"),
+            Some(Endgame::Scaled(SCALE_OPPOSITE_BISHOPS))
+        );
+        assert_eq!(
+            endgame("8/5k2/3b4/p7/P7/3B4/5K2/6N1 w - - 0 1"),
+            Some(Endgame::Scaled(SCALE_OPPOSITE_BISHOPS_WITH_PIECES))
+        );
+        // bishops on the same color aren't drawish
+        assert_eq!(endgame("8/5k2/2b5/p7/P7/3B4/5K2/8 w - - 0 1"), None);
+    }
+
+    #[test]
+    fn wrong_rook_pawn() {
+        // the light squared bishop can't drive the king off of h8
+        assert_eq!(
+            endgame("7k/8/8/8/7P/8/4B3/4K3 w - - 0 1"),
+            Some(Endgame::Scaled(0))
+        );
+        assert_eq!(
+            endgame("4k3/4b3/8/7p/8/8/8/7K w - - 0 1"),
+            Some(Endgame::Scaled(0))
+        );
+        // the right bishop, or a king too far away, wins
+        assert_eq!(endgame("7k/8/8/8/7P/8/5B2/4K3 w - - 0 1"), None);
+        assert_eq!(endgame("k7/8/8/8/7P/8/4B3/4K3 w - - 0 1"), None);
+        // a b pawn isn't a rook pawn
+        assert_eq!(endgame("7k/8/8/8/1P6/8/4B3/4K3 w - - 0 1"), None);
+    }
+}
@@ -1,2 +1,38 @@
+pub mod endgame;
+pub mod nnue;
+pub mod params;
+pub mod pawns;
+pub mod pieces;
 pub mod psqt;
-// I eventually do want to add NNUE.
+pub mod score;
+pub mod trace;
+
+// the same position with the board flipped and the colors swapped, which every evaluation should agree on
+#[cfg(test)]
+fn mirror_fen(fen: &str) -> String {
+    let fields = fen.split_whitespace().collect::<Vec<_>>();
+    let swap_case = |text: &str| {
+        text.chars()
+            .map(|character| {
+                if character.is_ascii_uppercase() {
+                    character.to_ascii_lowercase()
+                } else {
+                    character.to_ascii_uppercase()
+                }
+            })
+            .collect::<String>()
+    };
+    let placement = fields[0].split('/').rev().collect::<Vec<_>>().join("/");
+    let color = if fields[1] == "w" { "b" } else { "w" };
+    let passant = match fields[3].as_bytes() {
+        [file, rank] => format!("{}{}", *file as char, (b'1' + b'8' - rank) as char),
+        _ => String::from("-"),
+    };
+    format!(
+        "{} {color} {} {passant} {} {}",
+        swap_case(&placement),
+        swap_case(fields[2]),
+        fields[4],
+        fields[5]
+    )
+}
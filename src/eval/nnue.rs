@@ -0,0 +1,265 @@
+// A simple (768 -> N)x2 -> 1 perspective network, in the format bullet writes out for its
+// "simple" architecture. The file is a flat array of little endian i16s:
+//   feature weights [768][N], feature biases [N], output weights [2][N], output bias
+// optionally zero padded to a multiple of 64 bytes. The hidden size N is worked out from the file size.
+//
+// Each input feature is a (color, piece, square) triple seen from one side's point of view, so both
+// accumulators can be kept up to date as pieces are added and removed on the board.
+
+use std::{fs, sync::Arc};
+
+use anyhow::{bail, Context, Result};
+
+use crate::movegen::{
+    bitboard,
+    board::Board,
+    types::{Color, Piece, Square},
+};
+
+const NUM_FEATURES: usize = 768;
+const QA: i32 = 255;
+const QB: i32 = 64;
+const SCALE: i32 = 400;
+// keep the network well away from mate scores
+const MAX_EVAL: i32 = 9000;
+
+#[derive(Debug)]
+pub struct Network {
+    hidden_size: usize,
+    feature_weights: Vec<i16>,
+    feature_biases: Vec<i16>,
+    output_weights: Vec<i16>,
+    output_bias: i16,
+}
+
+impl Network {
+    pub fn load(path: &str) -> Result<Network> {
+        let bytes = fs::read(path).with_context(|| format!("Could not read {path}"))?;
+        if bytes.len() % 2 != 0 {
+            bail!("Invalid network file {path}")
+        }
+        let values = bytes
+            .chunks_exact(2)
+            .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+            .collect::<Vec<i16>>();
+        Self::from_values(&values).with_context(|| format!("Invalid network file {path}"))
+    }
+
+    fn from_values(values: &[i16]) -> Result<Network> {
+        // each hidden neuron has a weight for every feature, a bias, and an output weight per perspective
+        const PER_NEURON: usize = NUM_FEATURES + 3;
+        const MAX_PADDING: usize = 64 / 2;
+
+        let hidden_size = values.len().saturating_sub(1) / PER_NEURON;
+        let used = hidden_size * PER_NEURON + 1;
+        if hidden_size == 0 || values.len() - used >= MAX_PADDING {
+            bail!("unexpected size")
+        }
+        if values[used..].iter().any(|&padding| padding != 0) {
+            bail!("unexpected trailing data")
+        }
+
+        let (feature_weights, rest) = values.split_at(NUM_FEATURES * hidden_size);
+        let (feature_biases, rest) = rest.split_at(hidden_size);
+        let (output_weights, rest) = rest.split_at(2 * hidden_size);
+        Ok(Network {
+            hidden_size,
+            feature_weights: feature_weights.to_vec(),
+            feature_biases: feature_biases.to_vec(),
+            output_weights: output_weights.to_vec(),
+            output_bias: rest[0],
+        })
+    }
+
+    #[inline]
+    fn feature_weights(&self, feature: usize) -> &[i16] {
+        &self.feature_weights[feature * self.hidden_size..(feature + 1) * self.hidden_size]
+    }
+}
+
+// The network together with one accumulator per perspective. Lives on the board, which
+// updates it whenever a piece is added or removed, so unmaking a move undoes its changes too.
+#[derive(Clone, Debug)]
+pub struct Nnue {
+    network: Arc<Network>,
+    accumulators: [Vec<i16>; 2],
+}
+
+impl Nnue {
+    pub fn new(network: Arc<Network>, board: &Board) -> Self {
+        let mut nnue = Self {
+            accumulators: [
+                network.feature_biases.clone(),
+                network.feature_biases.clone(),
+            ],
+            network,
+        };
+        for color in [Color::W, Color::B] {
+            for (piecetype, bb) in board.pieces().iter().enumerate() {
+                let mut pieces = bb & board.color_bb(color);
+                while pieces > 0 {
+                    let square = bitboard::pop_lsb(&mut pieces);
+                    nnue.set_piece(square, PIECES[piecetype], color);
+                }
+            }
+        }
+        nnue
+    }
+
+    pub fn network(&self) -> Arc<Network> {
+        Arc::clone(&self.network)
+    }
+
+    #[inline]
+    pub fn set_piece(&mut self, square: Square, piece: Piece, color: Color) {
+        for perspective in [Color::W, Color::B] {
+            let weights =
+                self.network
+                    .feature_weights(feature_index(perspective, square, piece, color));
+            let accumulator = &mut self.accumulators[perspective as usize];
+            for (value, &weight) in accumulator.iter_mut().zip(weights) {
+                *value += weight;
+            }
+        }
+    }
+
+    #[inline]
+    pub fn remove_piece(&mut self, square: Square, piece: Piece, color: Color) {
+        for perspective in [Color::W, Color::B] {
+            let weights =
+                self.network
+                    .feature_weights(feature_index(perspective, square, piece, color));
+            let accumulator = &mut self.accumulators[perspective as usize];
+            for (value, &weight) in accumulator.iter_mut().zip(weights) {
+                *value -= weight;
+            }
+        }
+    }
+
+    // evaluation from the side to move's point of view
+    pub fn evaluate(&self, side_to_move: Color) -> i16 {
+        let us = &self.accumulators[side_to_move as usize];
+        let them = &self.accumulators[!side_to_move as usize];
+        let (our_weights, their_weights) = self
+            .network
+            .output_weights
+            .split_at(self.network.hidden_size);
+
+        let mut output = 0;
+        for (&value, &weight) in us.iter().zip(our_weights) {
+            output += crelu(value) * weight as i32;
+        }
+        for (&value, &weight) in them.iter().zip(their_weights) {
+            output += crelu(value) * weight as i32;
+        }
+        let eval = (output + self.network.output_bias as i32) * SCALE / (QA * QB);
+        eval.clamp(-MAX_EVAL, MAX_EVAL) as i16
+    }
+}
+
+const PIECES: [Piece; 6] = [Piece::P, Piece::N, Piece::B, Piece::R, Piece::Q, Piece::K];
+
+// from black's perspective, the board is flipped and the colors are swapped
+#[inline]
+fn feature_index(perspective: Color, square: Square, piece: Piece, color: Color) -> usize {
+    let (color, square) = match perspective {
+        Color::W => (color, square),
+        Color::B => (!color, square ^ 56),
+    };
+    color as usize * 384 + piece as usize * 64 + square as usize
+}
+
+#[inline]
+fn crelu(value: i16) -> i32 {
+    (value as i32).clamp(0, QA)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+    use crate::eval::pawns::PawnTable;
+
+    const HIDDEN_SIZE: usize = 16;
+
+    // small weights, so the accumulators stay well inside an i16
+    fn random_network() -> Arc<Network> {
+        let mut rng = StdRng::seed_from_u64(1);
+        let values = (0..HIDDEN_SIZE * (NUM_FEATURES + 3) + 1)
+            .map(|_| rng.gen_range(-40..40))
+            .collect::<Vec<i16>>();
+        Arc::new(Network::from_values(&values).unwrap())
+    }
+
+    fn board(fen: &str, network: &Arc<Network>) -> Board {
+        let mut board = Board::new();
+        board.parse_fen(fen).unwrap();
+        board.set_network(Some(Arc::clone(network)));
+        board
+    }
+
+    #[test]
+    fn file_size() {
+        let values = vec![0; HIDDEN_SIZE * (NUM_FEATURES + 3) + 1];
+        assert_eq!(
+            Network::from_values(&values).unwrap().hidden_size,
+            HIDDEN_SIZE
+        );
+        // zero padding up to 64 bytes is allowed
+        let mut padded = values.clone();
+        padded.extend([0; 31]);
+        assert!(Network::from_values(&padded).is_ok());
+        padded.push(0);
+        assert!(Network::from_values(&padded).is_err());
+        let mut trailing = values.clone();
+        trailing.push(1);
+        assert!(Network::from_values(&trailing).is_err());
+        assert!(Network::from_values(&values[..NUM_FEATURES]).is_err());
+    }
+
+    // making and unmaking moves keeps the accumulators the same as building them from scratch
+    #[test]
+    fn incremental_updates() {
+        let network = random_network();
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let mut board = board(fen, &network);
+        let start = board.evaluate(&mut PawnTable::new());
+
+        // castling, a capture, a double push with en passant, and a promotion
+        let moves = ["e1g1", "h3g2", "a2a4", "b4a3", "e5f7", "g2f1q"];
+        let mut actions = Vec::new();
+        for movestring in moves {
+            let action = board.parse_move(movestring).unwrap();
+            board.make_move(action);
+            actions.push(action);
+            let fresh = self::board(&board.to_fen(), &network);
+            assert_eq!(
+                board.evaluate(&mut PawnTable::new()),
+                fresh.evaluate(&mut PawnTable::new()),
+                "after {movestring}"
+            );
+        }
+        for action in actions.into_iter().rev() {
+            board.unmake_move(action);
+        }
+        assert_eq!(board.evaluate(&mut PawnTable::new()), start);
+    }
+
+    // both perspectives see the board the same way, so a mirrored position evaluates the same
+    #[test]
+    fn mirrored() {
+        let network = random_network();
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        ] {
+            let mirrored = board(&crate::eval::mirror_fen(fen), &network);
+            assert_eq!(
+                board(fen, &network).evaluate(&mut PawnTable::new()),
+                mirrored.evaluate(&mut PawnTable::new()),
+                "{fen}"
+            );
+        }
+    }
+}
@@ -0,0 +1,218 @@
+// Every hand-crafted evaluation parameter, laid out as one flat list so the tuner and the traced evaluation
+// can refer to them by index. The compiled in values are the pesto tables and the constants next to each
+// term, and a parameter file can replace any of them at runtime.
+//
+// A parameter file is plain text. Each line is a name followed by its values, and '#' starts a comment:
+//   PHASES 0 1 1 2 4 0
+//   MG_KNIGHT <64 values, laid out like the psqts>
+//   EG_KNIGHT <64 values>
+//   PASSED <8 middlegame and endgame pairs>
+//   BISHOP_PAIR 30 50
+// Anything left out keeps its compiled in value.
+
+use std::{collections::HashSet, fmt, fs, ops::Index};
+
+use anyhow::{bail, Context, Result};
+
+use super::{
+    pawns, pieces,
+    psqt::{self, TOTAL_PHASE},
+    score::Score,
+};
+
+pub const PSQT: usize = 0; // [piece][square], squares laid out like the psqts
+pub const PASSED: usize = PSQT + 6 * 64; // [relative rank]
+pub const DOUBLED: usize = PASSED + 8;
+pub const ISOLATED: usize = DOUBLED + 1;
+pub const BACKWARD: usize = ISOLATED + 1;
+pub const MOBILITY: usize = BACKWARD + 1; // [knight, bishop, rook, queen]
+pub const KING_ZONE_ATTACK: usize = MOBILITY + 4; // [knight, bishop, rook, queen]
+pub const BISHOP_PAIR: usize = KING_ZONE_ATTACK + 4;
+pub const ROOK_OPEN_FILE: usize = BISHOP_PAIR + 1;
+pub const ROOK_SEMI_OPEN_FILE: usize = ROOK_OPEN_FILE + 1;
+pub const KNIGHT_OUTPOST: usize = ROOK_SEMI_OPEN_FILE + 1;
+pub const BISHOP_OUTPOST: usize = KNIGHT_OUTPOST + 1;
+pub const NUM_PARAMS: usize = BISHOP_OUTPOST + 1;
+
+pub const PIECE_NAMES: [&str; 6] = ["PAWN", "KNIGHT", "BISHOP", "ROOK", "QUEEN", "KING"];
+// name, first parameter and number of parameters of everything besides the psqts
+pub const TERMS: [(&str, usize, usize); 11] = [
+    ("PASSED", PASSED, 8),
+    ("DOUBLED", DOUBLED, 1),
+    ("ISOLATED", ISOLATED, 1),
+    ("BACKWARD", BACKWARD, 1),
+    ("MOBILITY", MOBILITY, 4),
+    ("KING_ZONE_ATTACK", KING_ZONE_ATTACK, 4),
+    ("BISHOP_PAIR", BISHOP_PAIR, 1),
+    ("ROOK_OPEN_FILE", ROOK_OPEN_FILE, 1),
+    ("ROOK_SEMI_OPEN_FILE", ROOK_SEMI_OPEN_FILE, 1),
+    ("KNIGHT_OUTPOST", KNIGHT_OUTPOST, 1),
+    ("BISHOP_OUTPOST", BISHOP_OUTPOST, 1),
+];
+
+// keeps the sum of every term comfortably inside an i16
+const MAX_VALUE: i16 = 5000;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct EvalParams {
+    pub values: Vec<Score>,
+    pub phases: [i16; 6],
+}
+
+impl Default for EvalParams {
+    fn default() -> Self {
+        let mut values = vec![Score::default(); NUM_PARAMS];
+        for piece in 0..6 {
+            for square in 0..64 {
+                values[PSQT + piece * 64 + square] = Score::new(
+                    psqt::MG_TABLES[piece][square],
+                    psqt::EG_TABLES[piece][square],
+                );
+            }
+        }
+        values[PASSED..PASSED + 8].copy_from_slice(&pawns::PASSED);
+        values[DOUBLED] = pawns::DOUBLED;
+        values[ISOLATED] = pawns::ISOLATED;
+        values[BACKWARD] = pawns::BACKWARD;
+        values[MOBILITY..MOBILITY + 4].copy_from_slice(&pieces::MOBILITY);
+        values[KING_ZONE_ATTACK..KING_ZONE_ATTACK + 4].copy_from_slice(&pieces::KING_ZONE_ATTACK);
+        values[BISHOP_PAIR] = pieces::BISHOP_PAIR;
+        values[ROOK_OPEN_FILE] = pieces::ROOK_OPEN_FILE;
+        values[ROOK_SEMI_OPEN_FILE] = pieces::ROOK_SEMI_OPEN_FILE;
+        values[KNIGHT_OUTPOST] = pieces::KNIGHT_OUTPOST;
+        values[BISHOP_OUTPOST] = pieces::BISHOP_OUTPOST;
+        Self {
+            values,
+            phases: psqt::PHASES,
+        }
+    }
+}
+
+impl Index<usize> for EvalParams {
+    type Output = Score;
+
+    #[inline]
+    fn index(&self, param: usize) -> &Self::Output {
+        &self.values[param]
+    }
+}
+
+impl EvalParams {
+    pub fn load(path: &str) -> Result<EvalParams> {
+        let text = fs::read_to_string(path).with_context(|| format!("Could not read {path}"))?;
+        Self::parse(&text).with_context(|| format!("Invalid parameter file {path}"))
+    }
+
+    pub fn parse(text: &str) -> Result<EvalParams> {
+        let mut params = EvalParams::default();
+        let mut seen = HashSet::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            let Some(name) = fields.next() else {
+                continue;
+            };
+            let values = fields
+                .map(str::parse::<i16>)
+                .collect::<Result<Vec<i16>, _>>()
+                .with_context(|| {
+                    format!(
+                        "line {}: {name} has a value that isn't a number",
+                        number + 1
+                    )
+                })?;
+            params
+                .set(name, &values)
+                .with_context(|| format!("line {}", number + 1))?;
+            if !seen.insert(name) {
+                bail!("line {}: {name} is given twice", number + 1)
+            }
+        }
+        Ok(params)
+    }
+
+    #[inline]
+    pub fn psqt(&self, piece: usize, square: usize) -> Score {
+        self.values[PSQT + piece * 64 + square]
+    }
+
+    fn set(&mut self, name: &str, values: &[i16]) -> Result<()> {
+        if name == "PHASES" {
+            check_shape(name, values, 6)?;
+            if let Some(phase) = values
+                .iter()
+                .find(|phase| !(0..=TOTAL_PHASE).contains(*phase))
+            {
+                bail!("{name} has {phase}, outside of 0 to {TOTAL_PHASE}")
+            }
+            self.phases.copy_from_slice(values);
+            return Ok(());
+        }
+        if let Some(value) = values.iter().find(|value| value.abs() > MAX_VALUE) {
+            bail!("{name} has {value}, outside of -{MAX_VALUE} to {MAX_VALUE}")
+        }
+
+        // a psqt holds just the middlegame or just the endgame values
+        for (phase, prefix) in ["MG_", "EG_"].iter().enumerate() {
+            let piece = name
+                .strip_prefix(prefix)
+                .and_then(|piece_name| PIECE_NAMES.iter().position(|&name| name == piece_name));
+            if let Some(piece) = piece {
+                check_shape(name, values, 64)?;
+                for (square, &value) in values.iter().enumerate() {
+                    let score = &mut self.values[PSQT + piece * 64 + square];
+                    if phase == 0 {
+                        score.mg = value
+                    } else {
+                        score.eg = value
+                    }
+                }
+                return Ok(());
+            }
+        }
+
+        let (_, start, len) = TERMS
+            .iter()
+            .find(|(term, _, _)| *term == name)
+            .with_context(|| format!("unknown parameter {name}"))?;
+        check_shape(name, values, 2 * len)?;
+        for (param, pair) in values.chunks_exact(2).enumerate() {
+            self.values[start + param] = Score::new(pair[0], pair[1]);
+        }
+        Ok(())
+    }
+}
+
+fn check_shape(name: &str, values: &[i16], expected: usize) -> Result<()> {
+    if values.len() != expected {
+        bail!("{name} needs {expected} values, but has {}", values.len())
+    }
+    Ok(())
+}
+
+// writes out a complete parameter file
+impl fmt::Display for EvalParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let phases = self.phases.map(|phase| phase.to_string());
+        writeln!(f, "PHASES {}", phases.join(" "))?;
+        for (phase, prefix) in ["MG", "EG"].iter().enumerate() {
+            for (piece, piece_name) in PIECE_NAMES.iter().enumerate() {
+                let values = (0..64)
+                    .map(|square| {
+                        let score = self.psqt(piece, square);
+                        if phase == 0 { score.mg } else { score.eg }.to_string()
+                    })
+                    .collect::<Vec<String>>();
+                writeln!(f, "{prefix}_{piece_name} {}", values.join(" "))?;
+            }
+        }
+        for (name, start, len) in TERMS {
+            let values = self.values[start..start + len]
+                .iter()
+                .map(|score| format!("{} {}", score.mg, score.eg))
+                .collect::<Vec<String>>();
+            writeln!(f, "{name} {}", values.join(" "))?;
+        }
+        Ok(())
+    }
+}
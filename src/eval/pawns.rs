@@ -0,0 +1,271 @@
+// Pawn structure: passed, doubled, isolated and backward pawns. These only depend on where the pawns
+// are, so the result is cached in a per-thread table keyed by the pawn-only zobrist key.
+
+use crate::movegen::{
+    bitboard::{self, Bitboard, Direction},
+    board::Board,
+    types::{square, Color, Piece, Square},
+    zobrist::Zobrist,
+};
+
+use super::{
+    params,
+    score::Score,
+    trace::{NoTrace, Trace},
+};
+
+// default values. Indexed by rank, relative to the side the pawn belongs to
+#[rustfmt::skip]
+pub(super) const PASSED: [Score; 8] = [
+    Score::new(0, 0), Score::new(5, 10), Score::new(5, 15), Score::new(10, 25),
+    Score::new(20, 45), Score::new(35, 75), Score::new(60, 120), Score::new(0, 0),
+];
+pub(super) const DOUBLED: Score = Score::new(-10, -20);
+pub(super) const ISOLATED: Score = Score::new(-10, -15);
+pub(super) const BACKWARD: Score = Score::new(-8, -10);
+
+const PAWN_TABLE_SIZE: usize = 1 << 14;
+
+#[derive(Clone, Copy, Default)]
+struct PawnEntry {
+    key: Zobrist,
+    score: Score,
+}
+
+pub struct PawnTable {
+    entries: Vec<PawnEntry>,
+}
+
+impl Default for PawnTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PawnTable {
+    pub fn new() -> Self {
+        Self {
+            entries: vec![PawnEntry::default(); PAWN_TABLE_SIZE],
+        }
+    }
+
+    // scores were worked out with the old evaluation parameters
+    pub fn clear(&mut self) {
+        self.entries.fill(PawnEntry::default());
+    }
+
+    // Always-replace. A board without pawns has key 0, which matches the empty entries and their score of 0.
+    pub fn probe(&mut self, board: &Board) -> Score {
+        let key = board.pawn_zobrist();
+        let entry = &mut self.entries[key as usize % PAWN_TABLE_SIZE];
+        if entry.key != key {
+            *entry = PawnEntry {
+                key,
+                score: board.pawn_structure(&mut NoTrace),
+            };
+        }
+        entry.score
+    }
+}
+
+impl Board {
+    // from white's point of view
+    pub fn pawn_structure<T: Trace>(&self, trace: &mut T) -> Score {
+        evaluate_pawns(self, Color::W, trace) - evaluate_pawns(self, Color::B, trace)
+    }
+}
+
+fn evaluate_pawns<T: Trace>(board: &Board, us: Color, trace: &mut T) -> Score {
+    let params = board.params();
+    let our_pawns = board.piece_bb(Piece::P, us);
+    let their_pawns = board.piece_bb(Piece::P, !us);
+    let their_attacks = pawn_attacks(their_pawns, !us);
+
+    let mut score = Score::default();
+    let mut pawns = our_pawns;
+    while pawns > 0 {
+        let square = bitboard::pop_lsb(&mut pawns);
+        let pawn = bitboard::new_bb(square);
+        let file = bitboard::file_bb_of(square);
+        let adjacent_files =
+            bitboard::shift(file, Direction::E) | bitboard::shift(file, Direction::W);
+        let ahead = front_span(pawn, us);
+        // the squares in front of the pawn and diagonally in front of it, up to the last rank
+        let passed_span = front_span(
+            pawn | bitboard::shift(pawn, Direction::E) | bitboard::shift(pawn, Direction::W),
+            us,
+        );
+
+        let doubled = ahead & our_pawns != 0;
+        let isolated = adjacent_files & our_pawns == 0;
+        let passed = !doubled && passed_span & their_pawns == 0;
+        let backward = !isolated
+            && adjacent_files & our_pawns & level_or_behind(square, us) == 0
+            && bitboard::forward(pawn, us) & their_attacks != 0;
+
+        if passed {
+            let rank = relative_rank(square, us) as usize;
+            score += params[params::PASSED + rank];
+            trace.add(params::PASSED + rank, us, 1);
+        }
+        if doubled {
+            score += params[params::DOUBLED];
+            trace.add(params::DOUBLED, us, 1);
+        }
+        if isolated {
+            score += params[params::ISOLATED];
+            trace.add(params::ISOLATED, us, 1);
+        } else if backward {
+            score += params[params::BACKWARD];
+            trace.add(params::BACKWARD, us, 1);
+        }
+    }
+    score
+}
+
+pub(super) fn pawn_attacks(pawns: Bitboard, color: Color) -> Bitboard {
+    match color {
+        Color::W => bitboard::shift(pawns, Direction::NE) | bitboard::shift(pawns, Direction::NW),
+        Color::B => bitboard::shift(pawns, Direction::SE) | bitboard::shift(pawns, Direction::SW),
+    }
+}
+
+// every square in front of the pawns, not including the squares they are on
+pub(super) fn front_span(pawns: Bitboard, color: Color) -> Bitboard {
+    match color {
+        Color::W => {
+            let mut span = pawns << 8;
+            span |= span << 8;
+            span |= span << 16;
+            span | span << 32
+        }
+        Color::B => {
+            let mut span = pawns >> 8;
+            span |= span >> 8;
+            span |= span >> 16;
+            span | span >> 32
+        }
+    }
+}
+
+// the ranks from this square's rank back to the color's own back rank
+fn level_or_behind(square: Square, color: Color) -> Bitboard {
+    let rank = square::rank_of(square);
+    match color {
+        Color::W => u64::MAX >> (8 * (7 - rank)),
+        Color::B => u64::MAX << (8 * rank),
+    }
+}
+
+fn relative_rank(square: Square, color: Color) -> u8 {
+    match color {
+        Color::W => square::rank_of(square),
+        Color::B => 7 - square::rank_of(square),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::trace::EvalTrace;
+
+    // how often each pawn term was counted for white and black
+    fn counts(fen: &str) -> EvalTrace {
+        let mut board = Board::new();
+        board.parse_fen(fen).unwrap();
+        let mut trace = EvalTrace {
+            counts: vec![[0, 0]; params::NUM_PARAMS],
+            phase: 0,
+        };
+        board.pawn_structure(&mut trace);
+        trace
+    }
+
+    // the relative rank of every passed pawn, for white then black
+    fn passed(trace: &EvalTrace) -> [Vec<usize>; 2] {
+        [Color::W, Color::B].map(|color| {
+            (0..8)
+                .filter(|&rank| trace.counts[params::PASSED + rank][color as usize] > 0)
+                .collect()
+        })
+    }
+
+    #[test]
+    fn passed_pawns() {
+        let trace = counts("4k3/8/8/8/4P3/8/8/4K3 w - - 0 1");
+        assert_eq!(passed(&trace), [vec![3], vec![]]);
+
+        // an enemy pawn in front of it on a neighbouring file stops it from being passed, both ways
+        let trace = counts("4k3/8/8/3p4/4P3/8/8/4K3 w - - 0 1");
+        assert_eq!(passed(&trace), [vec![], vec![]]);
+        let trace = counts("4k3/8/5p2/8/4P3/8/8/4K3 w - - 0 1");
+        assert_eq!(passed(&trace), [vec![], vec![]]);
+
+        // but not one that is behind it
+        let trace = counts("4k3/8/8/8/4P3/3p4/8/4K3 w - - 0 1");
+        assert_eq!(passed(&trace), [vec![3], vec![5]]);
+
+        // the span doesn't wrap around the edge of the board
+        let trace = counts("4k3/8/8/7p/P7/8/8/4K3 w - - 0 1");
+        assert_eq!(passed(&trace), [vec![3], vec![3]]);
+        let trace = counts("4k3/8/8/1p6/P7/8/8/4K3 w - - 0 1");
+        assert_eq!(passed(&trace), [vec![], vec![]]);
+
+        // only the front pawn of a doubled pair
+        let trace = counts("4k3/8/8/8/4P3/4P3/8/4K3 w - - 0 1");
+        assert_eq!(passed(&trace), [vec![3], vec![]]);
+    }
+
+    #[test]
+    fn doubled_pawns() {
+        let trace = counts("4k3/8/8/8/4P3/4P3/8/4K3 w - - 0 1");
+        assert_eq!(trace.counts[params::DOUBLED], [1, 0]);
+        let trace = counts("4k3/2p5/2p5/2p5/8/8/8/4K3 w - - 0 1");
+        assert_eq!(trace.counts[params::DOUBLED], [0, 2]);
+        // pawns on different files aren't doubled
+        let trace = counts("4k3/8/8/8/4P3/3P4/8/4K3 w - - 0 1");
+        assert_eq!(trace.counts[params::DOUBLED], [0, 0]);
+    }
+
+    #[test]
+    fn isolated_pawns() {
+        let trace = counts("4k3/pp6/8/8/8/8/P1P2P1P/4K3 w - - 0 1");
+        assert_eq!(trace.counts[params::ISOLATED], [4, 0]);
+        let trace = counts("4k3/8/8/8/8/8/PP3PPP/4K3 w - - 0 1");
+        assert_eq!(trace.counts[params::ISOLATED], [0, 0]);
+    }
+
+    #[test]
+    fn backward_pawns() {
+        // e3 can't be supported by the d4 pawn, and black's f5 pawn guards e4
+        let trace = counts("4k3/8/8/5p2/3P4/4P3/8/4K3 w - - 0 1");
+        assert_eq!(trace.counts[params::BACKWARD], [1, 0]);
+        // a pawn level with it can still come to help
+        let trace = counts("4k3/8/8/5p2/8/3PP3/8/4K3 w - - 0 1");
+        assert_eq!(trace.counts[params::BACKWARD], [0, 0]);
+        // nothing stops it from advancing
+        let trace = counts("4k3/8/8/8/3P4/4P3/8/4K3 w - - 0 1");
+        assert_eq!(trace.counts[params::BACKWARD], [0, 0]);
+        // the same for black
+        let trace = counts("4k3/8/4p3/3p4/5P2/8/8/4K3 b - - 0 1");
+        assert_eq!(trace.counts[params::BACKWARD], [0, 1]);
+    }
+
+    #[test]
+    fn mirrored() {
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "4k3/pp3p2/2p3p1/3pP2p/P2P4/1P4PP/5P2/4K3 w - - 0 1",
+        ] {
+            let mut board = Board::new();
+            board.parse_fen(fen).unwrap();
+            let mut mirrored = Board::new();
+            mirrored.parse_fen(&super::super::mirror_fen(fen)).unwrap();
+            assert_eq!(
+                board.pawn_structure(&mut NoTrace),
+                -mirrored.pawn_structure(&mut NoTrace),
+                "{fen}"
+            );
+        }
+    }
+}
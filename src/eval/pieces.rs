@@ -0,0 +1,237 @@
+// Piece activity: mobility, pressure on the enemy king, the bishop pair, rooks on open files and outposts.
+// Unlike pawn structure, these depend on every piece on the board, so they are computed from scratch.
+
+use crate::movegen::{
+    atks,
+    bitboard::{self, Bitboard},
+    board::Board,
+    types::{Color, Piece, Square},
+};
+
+use super::{
+    params,
+    pawns::{front_span, pawn_attacks},
+    score::Score,
+    trace::Trace,
+};
+
+// knights, bishops, rooks and queens, in that order
+const PIECES: [Piece; 4] = [Piece::N, Piece::B, Piece::R, Piece::Q];
+
+// Default values, the ones in use live in the board's EvalParams.
+
+// per square a piece can move to, counted from a typical number of moves so material values stay put
+pub(super) const MOBILITY: [Score; 4] = [
+    Score::new(4, 4),
+    Score::new(5, 5),
+    Score::new(2, 4),
+    Score::new(1, 2),
+];
+const MOBILITY_BASE: [i16; 4] = [4, 7, 7, 14];
+
+// per attacked square around the enemy king. Only counts once at least two pieces join the attack.
+pub(super) const KING_ZONE_ATTACK: [Score; 4] = [
+    Score::new(6, 1),
+    Score::new(5, 1),
+    Score::new(7, 1),
+    Score::new(10, 2),
+];
+const MIN_KING_ATTACKERS: u32 = 2;
+
+pub(super) const BISHOP_PAIR: Score = Score::new(30, 50);
+pub(super) const ROOK_OPEN_FILE: Score = Score::new(25, 10);
+pub(super) const ROOK_SEMI_OPEN_FILE: Score = Score::new(12, 8);
+pub(super) const KNIGHT_OUTPOST: Score = Score::new(25, 10);
+pub(super) const BISHOP_OUTPOST: Score = Score::new(12, 5);
+
+// the 4th to 6th ranks, from each side's point of view
+const OUTPOST_RANKS: [Bitboard; 2] = [0x0000_ffff_ff00_0000, 0x0000_00ff_ffff_0000];
+
+impl Board {
+    // from white's point of view
+    pub fn piece_activity<T: Trace>(&self, trace: &mut T) -> Score {
+        evaluate_pieces(self, Color::W, trace) - evaluate_pieces(self, Color::B, trace)
+    }
+}
+
+fn evaluate_pieces<T: Trace>(board: &Board, us: Color, trace: &mut T) -> Score {
+    let params = board.params();
+    let them = !us;
+    let occupancy = board.occupancy();
+    let our_pawns = board.piece_bb(Piece::P, us);
+    let their_pawns = board.piece_bb(Piece::P, them);
+
+    // squares attacked by enemy pawns aren't really available to move to
+    let mobility_area = !board.color_bb(us) & !pawn_attacks(their_pawns, them);
+
+    let their_king = board.piece_bb(Piece::K, them).trailing_zeros() as Square;
+    let king_zone = atks::king_attacks(their_king) | bitboard::new_bb(their_king);
+
+    // squares defended by our pawns that no enemy pawn can ever attack
+    let their_pawn_reach = pawn_attacks(their_pawns | front_span(their_pawns, them), them);
+    let outposts = pawn_attacks(our_pawns, us) & !their_pawn_reach & OUTPOST_RANKS[us as usize];
+
+    let mut score = Score::default();
+    let mut king_attackers = 0;
+    let mut zone_attacks = [0; 4];
+    for (index, piece) in PIECES.into_iter().enumerate() {
+        let mut pieces = board.piece_bb(piece, us);
+        while pieces > 0 {
+            let square = bitboard::pop_lsb(&mut pieces);
+            let attacks = match piece {
+                Piece::N => atks::knight_attacks(square),
+                Piece::B => atks::bishop_attacks(square, occupancy),
+                Piece::R => atks::rook_attacks(square, occupancy),
+                _ => {
+                    atks::bishop_attacks(square, occupancy) | atks::rook_attacks(square, occupancy)
+                }
+            };
+
+            let mobility = (attacks & mobility_area).count_ones() as i16;
+            score += params[params::MOBILITY + index] * (mobility - MOBILITY_BASE[index]);
+            trace.add(
+                params::MOBILITY + index,
+                us,
+                mobility - MOBILITY_BASE[index],
+            );
+
+            let attacked = (attacks & king_zone).count_ones() as i16;
+            if attacked > 0 {
+                king_attackers += 1;
+                zone_attacks[index] += attacked;
+            }
+
+            let on_outpost = bitboard::new_bb(square) & outposts != 0;
+            match piece {
+                Piece::N if on_outpost => {
+                    score += params[params::KNIGHT_OUTPOST];
+                    trace.add(params::KNIGHT_OUTPOST, us, 1);
+                }
+                Piece::B if on_outpost => {
+                    score += params[params::BISHOP_OUTPOST];
+                    trace.add(params::BISHOP_OUTPOST, us, 1);
+                }
+                Piece::R => {
+                    let file = bitboard::file_bb_of(square);
+                    if file & (our_pawns | their_pawns) == 0 {
+                        score += params[params::ROOK_OPEN_FILE];
+                        trace.add(params::ROOK_OPEN_FILE, us, 1);
+                    } else if file & our_pawns == 0 {
+                        score += params[params::ROOK_SEMI_OPEN_FILE];
+                        trace.add(params::ROOK_SEMI_OPEN_FILE, us, 1);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    if king_attackers >= MIN_KING_ATTACKERS {
+        for (index, &attacked) in zone_attacks.iter().enumerate() {
+            score += params[params::KING_ZONE_ATTACK + index] * attacked;
+            trace.add(params::KING_ZONE_ATTACK + index, us, attacked);
+        }
+    }
+    if board.piece_bb(Piece::B, us).count_ones() >= 2 {
+        score += params[params::BISHOP_PAIR];
+        trace.add(params::BISHOP_PAIR, us, 1);
+    }
+    score
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::trace::{EvalTrace, NoTrace};
+
+    fn board(fen: &str) -> Board {
+        let mut board = Board::new();
+        board.parse_fen(fen).unwrap();
+        board
+    }
+
+    fn counts(fen: &str) -> EvalTrace {
+        let mut trace = EvalTrace {
+            counts: vec![[0, 0]; params::NUM_PARAMS],
+            phase: 0,
+        };
+        board(fen).piece_activity(&mut trace);
+        trace
+    }
+
+    #[test]
+    fn bishop_pair() {
+        let trace = counts("4k3/8/8/8/8/8/8/2B1KB2 w - - 0 1");
+        assert_eq!(trace.counts[params::BISHOP_PAIR], [1, 0]);
+        let trace = counts("2b1kn2/8/8/8/8/8/8/2B1KB2 w - - 0 1");
+        assert_eq!(trace.counts[params::BISHOP_PAIR], [1, 0]);
+    }
+
+    #[test]
+    fn rook_files() {
+        // the a file only has an enemy pawn on it, the c file has none
+        let trace = counts("4k3/p7/8/8/8/8/1P6/R1R1K3 w - - 0 1");
+        assert_eq!(trace.counts[params::ROOK_OPEN_FILE], [1, 0]);
+        assert_eq!(trace.counts[params::ROOK_SEMI_OPEN_FILE], [1, 0]);
+        // behind its own pawn, a rook gets neither
+        let trace = counts("4k3/8/8/8/8/8/1P6/1R2K3 w - - 0 1");
+        assert_eq!(trace.counts[params::ROOK_OPEN_FILE], [0, 0]);
+        assert_eq!(trace.counts[params::ROOK_SEMI_OPEN_FILE], [0, 0]);
+    }
+
+    #[test]
+    fn outposts() {
+        let trace = counts("4k3/8/8/3N4/4P3/8/8/4K3 w - - 0 1");
+        assert_eq!(trace.counts[params::KNIGHT_OUTPOST], [1, 0]);
+        // a pawn on c7 could still chase it away
+        let trace = counts("4k3/2p5/8/3N4/4P3/8/8/4K3 w - - 0 1");
+        assert_eq!(trace.counts[params::KNIGHT_OUTPOST], [0, 0]);
+        // but not one that has already gone past
+        let trace = counts("4k3/8/8/3N4/2pP4/8/8/4K3 w - - 0 1");
+        assert_eq!(trace.counts[params::KNIGHT_OUTPOST], [0, 0]);
+        let trace = counts("4k3/8/8/3B4/4P3/2p5/8/4K3 w - - 0 1");
+        assert_eq!(trace.counts[params::BISHOP_OUTPOST], [1, 0]);
+        // without pawn support it isn't an outpost
+        let trace = counts("4k3/8/8/3N4/8/8/8/4K3 w - - 0 1");
+        assert_eq!(trace.counts[params::KNIGHT_OUTPOST], [0, 0]);
+    }
+
+    #[test]
+    fn king_attacks() {
+        let zone_attacks = |trace: &EvalTrace| {
+            [0, 1, 2, 3].map(|index| trace.counts[params::KING_ZONE_ATTACK + index][0])
+        };
+        // the rook sees f7 and f8, the queen h7 and h8
+        let trace = counts("6k1/8/8/8/8/7Q/8/4KR2 w - - 0 1");
+        assert_eq!(zone_attacks(&trace), [0, 0, 2, 2]);
+        // a lone attacker doesn't count
+        let trace = counts("6k1/8/8/8/8/8/8/4KR2 w - - 0 1");
+        assert_eq!(zone_attacks(&trace), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn mobility() {
+        // a knight in the corner reaches two squares, four fewer than the baseline
+        let trace = counts("4k3/8/8/8/8/8/8/N3K3 w - - 0 1");
+        assert_eq!(trace.counts[params::MOBILITY], [2 - MOBILITY_BASE[0], 0]);
+        // squares guarded by enemy pawns don't count
+        let trace = counts("4k3/8/8/8/8/1p6/8/N3K3 w - - 0 1");
+        assert_eq!(trace.counts[params::MOBILITY], [1 - MOBILITY_BASE[0], 0]);
+    }
+
+    #[test]
+    fn mirrored() {
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+            "6k1/8/8/8/8/7Q/8/4KR2 w - - 0 1",
+        ] {
+            let mirrored = board(&super::super::mirror_fen(fen));
+            assert_eq!(
+                board(fen).piece_activity(&mut NoTrace),
+                -mirrored.piece_activity(&mut NoTrace),
+                "{fen}"
+            );
+        }
+    }
+}
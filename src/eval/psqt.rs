@@ -2,13 +2,15 @@ use std::cmp;
 
 use crate::movegen::{types::{Square, Piece, Color, square}, board::Board, bitboard};
 
+use super::{params::EvalParams, score::Score};
+
 // pesto psqts
 const PAWN_PHASE: i16 = 0;
 const KNIGHT_PHASE: i16 = 1;
 const BISHOP_PHASE: i16 = 1;
 const ROOK_PHASE: i16 = 2;
 const QUEEN_PHASE: i16 = 4;
-const TOTAL_PHASE: i16 = 24;
+pub const TOTAL_PHASE: i16 = 24;
 pub const PHASES: [i16; 6] = [
     PAWN_PHASE,
     KNIGHT_PHASE,
@@ -156,9 +158,9 @@ const EG_KING: [i16; 64] = [
     -53, -34, -21, -11, -28, -14, -24, -43,
 ];
 
-const MG_TABLES: [[i16; 64]; 6] = [MG_PAWN, MG_KNIGHT, MG_BISHOP, MG_ROOK, MG_QUEEN, MG_KING];
+pub(super) const MG_TABLES: [[i16; 64]; 6] = [MG_PAWN, MG_KNIGHT, MG_BISHOP, MG_ROOK, MG_QUEEN, MG_KING];
 
-const EG_TABLES: [[i16; 64]; 6] = [EG_PAWN, EG_KNIGHT, EG_BISHOP, EG_ROOK, EG_QUEEN, EG_KING];
+pub(super) const EG_TABLES: [[i16; 64]; 6] = [EG_PAWN, EG_KNIGHT, EG_BISHOP, EG_ROOK, EG_QUEEN, EG_KING];
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct IncrementalEval {
@@ -188,19 +190,19 @@ impl Board {
             let mut black_pieces = bb & blacks;
             while white_pieces > 0 {
                 let square = square::flip_v(bitboard::pop_lsb(&mut white_pieces));
-                let mg_value = MG_TABLES[piecetype][square as usize];
-                let eg_value = EG_TABLES[piecetype][square as usize];
+                let value = self.params().psqt(piecetype, square as usize);
+                let (mg_value, eg_value) = (value.mg, value.eg);
                 white_mg_material += mg_value;
                 white_eg_material += eg_value;
-                phase += PHASES[piecetype];
+                phase += self.params().phases[piecetype];
             }
             while black_pieces > 0 {
                 let square = bitboard::pop_lsb(&mut black_pieces);
-                let mg_value = MG_TABLES[piecetype][square as usize];
-                let eg_value = EG_TABLES[piecetype][square as usize];
+                let value = self.params().psqt(piecetype, square as usize);
+                let (mg_value, eg_value) = (value.mg, value.eg);
                 black_mg_material += mg_value;
                 black_eg_material += eg_value;
-                phase += PHASES[piecetype];
+                phase += self.params().phases[piecetype];
             }
         }
         IncrementalEval {
@@ -211,7 +213,7 @@ impl Board {
     }
 }
 
-const FLIPS: [u8; 2] = [56, 0]; // pesto psqts are mirrored
+pub(super) const FLIPS: [u8; 2] = [56, 0]; // pesto psqts are mirrored
 impl IncrementalEval {
     pub fn new() -> Self {
         Self {
@@ -221,29 +223,42 @@ impl IncrementalEval {
         }
     }
 
-    pub fn set_piece(&mut self, square: Square, piece: Piece, color: Color) {
-        self.phase += PHASES[piece as usize];
+    pub fn set_piece(&mut self, params: &EvalParams, square: Square, piece: Piece, color: Color) {
+        self.phase += params.phases[piece as usize];
         let square = square ^ FLIPS[color as usize];
-        let mg_value = MG_TABLES[piece as usize][square as usize];
-        let eg_value = EG_TABLES[piece as usize][square as usize];
+        let value = params.psqt(piece as usize, square as usize);
+        let (mg_value, eg_value) = (value.mg, value.eg);
         self.mg_material[color as usize] += mg_value;
         self.eg_material[color as usize] += eg_value;
     }
 
-    pub fn remove_piece(&mut self, square: Square, piece: Piece, color: Color) {
-        self.phase -= PHASES[piece as usize];
+    pub fn remove_piece(
+        &mut self,
+        params: &EvalParams,
+        square: Square,
+        piece: Piece,
+        color: Color,
+    ) {
+        self.phase -= params.phases[piece as usize];
         let square = square ^ FLIPS[color as usize];
-        let mg_value = MG_TABLES[piece as usize][square as usize];
-        let eg_value = EG_TABLES[piece as usize][square as usize];
+        let value = params.psqt(piece as usize, square as usize);
+        let (mg_value, eg_value) = (value.mg, value.eg);
         self.mg_material[color as usize] -= mg_value;
         self.eg_material[color as usize] -= eg_value;
     }
 
-    pub fn evaluate(&self) -> i16 {
-        let mg_score = self.mg_material[0] as i32 - self.mg_material[1] as i32;
-        let eg_score = self.eg_material[0] as i32 - self.eg_material[1] as i32;
+    // material and placement from white's point of view
+    pub fn score(&self) -> Score {
+        Score::new(
+            self.mg_material[0] - self.mg_material[1],
+            self.eg_material[0] - self.eg_material[1],
+        )
+    }
+
+    // blends middlegame and endgame values by how much material is left
+    pub fn taper(&self, score: Score) -> i16 {
         let mg_phase = cmp::min(self.phase, TOTAL_PHASE) as i32;
         let eg_phase = cmp::max(TOTAL_PHASE as i32 - mg_phase, 0);
-        (((mg_score * mg_phase) + (eg_score * eg_phase)) / (TOTAL_PHASE as i32)) as i16
+        (((score.mg as i32 * mg_phase) + (score.eg as i32 * eg_phase)) / (TOTAL_PHASE as i32)) as i16
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,58 @@
+use std::ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign};
+
+// a middlegame and endgame value pair, blended together by the game phase at the end of evaluation
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Score {
+    pub mg: i16,
+    pub eg: i16,
+}
+
+impl Score {
+    pub const fn new(mg: i16, eg: i16) -> Self {
+        Self { mg, eg }
+    }
+}
+
+impl Add for Score {
+    type Output = Score;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Score::new(self.mg + rhs.mg, self.eg + rhs.eg)
+    }
+}
+
+impl AddAssign for Score {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs
+    }
+}
+
+impl Sub for Score {
+    type Output = Score;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Score::new(self.mg - rhs.mg, self.eg - rhs.eg)
+    }
+}
+
+impl SubAssign for Score {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = *self - rhs
+    }
+}
+
+impl Neg for Score {
+    type Output = Score;
+
+    fn neg(self) -> Self::Output {
+        Score::new(-self.mg, -self.eg)
+    }
+}
+
+impl Mul<i16> for Score {
+    type Output = Score;
+
+    fn mul(self, rhs: i16) -> Self::Output {
+        Score::new(self.mg * rhs, self.eg * rhs)
+    }
+}
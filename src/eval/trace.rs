@@ -0,0 +1,133 @@
+// A traced evaluation records how many times each parameter was counted for each side. That is all the
+// tuner needs to fit them to game results, and lets the eval command break the evaluation down by term.
+
+use std::fmt::{Display, Write};
+
+use crate::movegen::{bitboard, board::Board, types::Color};
+
+use super::{
+    params::{EvalParams, NUM_PARAMS, PSQT, TERMS},
+    psqt::{FLIPS, TOTAL_PHASE},
+    score::Score,
+};
+
+pub trait Trace {
+    fn add(&mut self, param: usize, color: Color, count: i16);
+}
+
+// what the search uses. Compiles down to nothing.
+pub struct NoTrace;
+
+impl Trace for NoTrace {
+    #[inline(always)]
+    fn add(&mut self, _param: usize, _color: Color, _count: i16) {}
+}
+
+#[derive(Clone, Debug)]
+pub struct EvalTrace {
+    pub counts: Vec<[i16; 2]>,
+    pub phase: i16,
+}
+
+impl Trace for EvalTrace {
+    fn add(&mut self, param: usize, color: Color, count: i16) {
+        self.counts[param][color as usize] += count;
+    }
+}
+
+impl EvalTrace {
+    // white's count minus black's count
+    pub fn coefficient(&self, param: usize) -> i16 {
+        self.counts[param][Color::W as usize] - self.counts[param][Color::B as usize]
+    }
+
+    // what a group of parameters added up to for one side
+    pub fn term(&self, params: &EvalParams, start: usize, len: usize, color: Color) -> Score {
+        (start..start + len).fold(Score::default(), |total, param| {
+            total + params[param] * self.counts[param][color as usize]
+        })
+    }
+}
+
+impl Board {
+    // the hand-crafted evaluation from white's point of view, along with what went into it
+    pub fn trace_eval(&self) -> (i16, EvalTrace) {
+        let evalinfo = self.generate_eval();
+        let mut trace = EvalTrace {
+            counts: vec![[0, 0]; NUM_PARAMS],
+            phase: evalinfo.phase,
+        };
+        for color in [Color::W, Color::B] {
+            for (piecetype, bb) in self.pieces().iter().enumerate() {
+                let mut pieces = bb & self.color_bb(color);
+                while pieces > 0 {
+                    let square = bitboard::pop_lsb(&mut pieces) ^ FLIPS[color as usize];
+                    trace.add(PSQT + piecetype * 64 + square as usize, color, 1);
+                }
+            }
+        }
+        let score =
+            evalinfo.score() + self.pawn_structure(&mut trace) + self.piece_activity(&mut trace);
+        (evalinfo.taper(score), trace)
+    }
+
+    // a table of every term for each side, for the eval command
+    pub fn eval_breakdown(&self) -> String {
+        let (eval, trace) = self.trace_eval();
+        let params = self.params();
+        let rule = "-".repeat(71);
+
+        let mut table = String::new();
+        writeln!(
+            table,
+            "{:>20} | {:^13} | {:^13} | {:^13}",
+            "term", "white", "black", "total"
+        )
+        .unwrap();
+        write_row(&mut table, "", ["mg", "eg", "mg", "eg", "mg", "eg"]);
+        writeln!(table, "{rule}").unwrap();
+
+        let (mut white_total, mut black_total) = (Score::default(), Score::default());
+        let terms = [("MATERIAL_AND_PSQT", PSQT, 6 * 64)]
+            .into_iter()
+            .chain(TERMS);
+        for (name, start, len) in terms {
+            let white = trace.term(params, start, len, Color::W);
+            let black = trace.term(params, start, len, Color::B);
+            write_scores(
+                &mut table,
+                &name.to_lowercase().replace('_', " "),
+                white,
+                black,
+            );
+            white_total += white;
+            black_total += black;
+        }
+        writeln!(table, "{rule}").unwrap();
+        write_scores(&mut table, "total", white_total, black_total);
+
+        writeln!(
+            table,
+            "phase {}/{TOTAL_PHASE}",
+            trace.phase.min(TOTAL_PHASE)
+        )
+        .unwrap();
+        writeln!(table, "tapered evaluation {eval} (white's point of view)").unwrap();
+        table
+    }
+}
+
+fn write_scores(table: &mut String, name: &str, white: Score, black: Score) {
+    let total = white - black;
+    let values = [white.mg, white.eg, black.mg, black.eg, total.mg, total.eg];
+    write_row(table, name, values.map(|value| value.to_string()));
+}
+
+fn write_row<T: Display>(table: &mut String, name: &str, values: [T; 6]) {
+    let [white_mg, white_eg, black_mg, black_eg, total_mg, total_eg] = values;
+    writeln!(
+        table,
+        "{name:>20} | {white_mg:>6} {white_eg:>6} | {black_mg:>6} {black_eg:>6} | {total_mg:>6} {total_eg:>6}"
+    )
+    .unwrap();
+}
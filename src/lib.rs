@@ -0,0 +1,19 @@
+// Shen Yu's move generation, evaluation and search, for tools to build on. The ShenYu binary is a uci frontend
+// on top of this.
+
+pub mod eval;
+pub mod movegen;
+pub mod search;
+pub mod syzygy;
+
+pub use movegen::{
+    action::{Action, MoveType},
+    board::Board,
+    genmoves::GenType,
+    movelist::MoveList,
+    types::{Color, Piece, Square},
+};
+pub use search::{
+    searchcontrol::{Bound, Iteration, SearchCommand, SearchInfo, SearchResult, Searcher},
+    timer::SearchLimits,
+};
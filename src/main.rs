@@ -1,8 +1,10 @@
+use std::env;
+
 use uci::{gameloop, VERSION};
 
-mod eval;
-mod movegen;
-pub mod search;
+mod epd;
+mod pgn;
+mod tune;
 mod uci;
 
 fn main() {
@@ -25,5 +27,24 @@ fn main() {
 "
     );
     println!("ShenYu v{VERSION} by Aaron Li");
-    gameloop();
+
+    let mut args = env::args().skip(1);
+    match args.next().as_deref() {
+        Some("epd") => {
+            if let Err(error) = epd::run(args) {
+                println!("{error:#}");
+            }
+        }
+        Some("tune") => {
+            if let Err(error) = tune::run(args) {
+                println!("{error:#}");
+            }
+        }
+        Some("pgn") => {
+            if let Err(error) = pgn::run(args) {
+                println!("{error:#}");
+            }
+        }
+        _ => gameloop(),
+    }
 }
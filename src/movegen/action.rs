@@ -1,11 +1,7 @@
-use std::{
-    fmt::{self, Write},
-    mem,
-    ops::Deref,
-};
+use std::{fmt, mem, ops::Deref};
 
 use super::{
-    board::Board,
+    board::{Board, Castling},
     types::{square, Piece, Square},
 };
 
@@ -61,10 +57,23 @@ pub enum MoveType {
     Passant,
 }
 
+// the move as uci writes it outside of chess960
 impl fmt::Display for Action {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", square::name(self.from()))?;
-        write!(f, "{}", square::name(self.to()))?;
+        f.write_str(&self.to_uci(false))
+    }
+}
+
+impl Action {
+    // Castling is stored as the king capturing its own rook, which stays unambiguous in chess960. Outside of
+    // chess960 it is written as the usual two square king move.
+    pub fn to_uci(self, chess960: bool) -> String {
+        let to = if self.move_type() == MoveType::Castle && !chess960 {
+            castling_destinations(self.from(), self.to()).0
+        } else {
+            self.to()
+        };
+        let mut uci = format!("{}{}", square::name(self.from()), square::name(to));
         if self.move_type() == MoveType::Promotion {
             let pr_char = match self.pr_piece() {
                 Piece::N => 'n',
@@ -73,13 +82,11 @@ impl fmt::Display for Action {
                 Piece::Q => 'q',
                 _ => panic!("This shouldn't happen"),
             };
-            f.write_char(pr_char)?;
+            uci.push(pr_char);
         }
-        Ok(())
+        uci
     }
-}
 
-impl Action {
     pub fn new(from: Square, to: Square) -> Self {
         let num = (from as u16) | (to as u16) << 6;
         Self(num)
@@ -119,6 +126,7 @@ impl Board {
     pub fn make_nullmove(&mut self) {
         self.push_info();
         self.reset_fifty();
+        self.increment_fullmove();
         self.reset_passant();
         self.swap_sides();
     }
@@ -131,6 +139,7 @@ impl Board {
     pub fn make_move(&mut self, action: Action) {
         self.push_info(); // create a new info store
         self.increment_fifty();
+        self.increment_fullmove();
         self.reset_passant();
         let (from, to, move_type) = (action.from(), action.to(), action.move_type());
         let (us, them) = (self.active_color(), !self.active_color());
@@ -167,11 +176,16 @@ impl Board {
                 self.move_piece::<true>(from, to, moving_piece, us)
             }
             MoveType::Castle => {
-                update_castle = true;
-                let (rook_from, rook_to) = get_castling_squares(to);
-
-                self.move_piece::<true>(from, to, Piece::K, us);
-                self.move_piece::<true>(rook_from, rook_to, Piece::R, us);
+                // both pieces are lifted first, since in chess960 they can land on each other's squares
+                let (king_to, rook_to) = castling_destinations(from, to);
+                self.remove_piece::<true>(from, Piece::K, us);
+                self.remove_piece::<true>(to, Piece::R, us);
+                self.add_piece::<true>(king_to, Piece::K, us);
+                self.add_piece::<true>(rook_to, Piece::R, us);
+
+                // the king doesn't always leave its square in chess960, so the rights are dropped here
+                self.set_castling(Castling::new(us, true), false);
+                self.set_castling(Castling::new(us, false), false);
             }
             MoveType::Promotion => {
                 // again, check for capture
@@ -215,9 +229,11 @@ impl Board {
                 self.restore_piece(to, them);
             }
             MoveType::Castle => {
-                let (rook_from, rook_to) = get_castling_squares(to);
-                self.move_piece::<false>(to, from, Piece::K, us);
-                self.move_piece::<false>(rook_to, rook_from, Piece::R, us);
+                let (king_to, rook_to) = castling_destinations(from, to);
+                self.remove_piece::<false>(king_to, Piece::K, us);
+                self.remove_piece::<false>(rook_to, Piece::R, us);
+                self.add_piece::<false>(from, Piece::K, us);
+                self.add_piece::<false>(to, Piece::R, us);
             }
             MoveType::Promotion => {
                 self.remove_piece::<false>(to, action.pr_piece(), us);
@@ -233,30 +249,12 @@ impl Board {
         self.pop_info()
     }
 }
-// based on destination squares of castling, get rook from and rook to
-fn get_castling_squares(destination: Square) -> (Square, Square) {
-    let rook_from: Square;
-    let rook_to: Square;
-
-    match destination {
-        square::G1 => {
-            rook_from = square::H1;
-            rook_to = square::F1;
-        }
-        square::G8 => {
-            rook_from = square::H8;
-            rook_to = square::F8;
-        }
-        square::C1 => {
-            rook_from = square::A1;
-            rook_to = square::D1;
-        }
-        square::C8 => {
-            rook_from = square::A8;
-            rook_to = square::D8;
-        }
-        _ => panic!("Invalid castling"),
+// based on where the king and rook start, get king to and rook to. The rook's side decides the direction
+pub(super) fn castling_destinations(king_from: Square, rook_from: Square) -> (Square, Square) {
+    let rank = square::rank_of(king_from);
+    if rook_from > king_from {
+        (square::new_sq(rank, 6), square::new_sq(rank, 5))
+    } else {
+        (square::new_sq(rank, 2), square::new_sq(rank, 3))
     }
-
-    (rook_from, rook_to)
 }
@@ -356,15 +356,17 @@ mod in_betweens {
         const A2A7: u64 = 0x0001010101010100;
         const B2G7: u64 = 0x0040201008040200;
         const H1B7: u64 = 0x0002040810204080;
+        // the subtractions are meant to wrap, only the low bits of file and rank are used
+        let (square1, square2) = (square1 as u64, square2 as u64);
         let (mut line, btwn, rank, file): (u64, u64, u64, u64);
         btwn = (M1 << square1) ^ (M1 << square2);
-        file = ((square2 & 7) - (square1 & 7)) as u64;
-        rank = (((square2 | 7) - square1) >> 3) as u64;
-        line = ((file & 7) - 1) & A2A7;
-        line += 2 * (((rank & 7) - 1) >> 58);
-        line += (((rank - file) & 15) - 1) & B2G7;
-        line += (((rank + file) & 15) - 1) & H1B7;
-        line *= btwn & btwn.wrapping_neg();
+        file = (square2 & 7).wrapping_sub(square1 & 7);
+        rank = (square2 | 7).wrapping_sub(square1) >> 3;
+        line = (file & 7).wrapping_sub(1) & A2A7;
+        line += 2 * ((rank & 7).wrapping_sub(1) >> 58);
+        line += (rank.wrapping_sub(file) & 15).wrapping_sub(1) & B2G7;
+        line += (rank.wrapping_add(file) & 15).wrapping_sub(1) & H1B7;
+        line = line.wrapping_mul(btwn & btwn.wrapping_neg());
         line & btwn
     }
 }
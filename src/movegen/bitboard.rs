@@ -19,7 +19,7 @@ pub(super) const fn rank_bb_of(square: Square) -> Bitboard {
 }
 
 #[inline]
-pub(super) const fn file_bb_of(square: Square) -> Bitboard {
+pub(crate) const fn file_bb_of(square: Square) -> Bitboard {
     0x101010101010101 << square::file_of(square)
 }
 
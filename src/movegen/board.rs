@@ -1,4 +1,13 @@
-use crate::eval::psqt::IncrementalEval;
+use std::sync::Arc;
+
+use crate::eval::{
+    endgame::{Endgame, SCALE_NORMAL},
+    nnue::{Network, Nnue},
+    params::EvalParams,
+    pawns::PawnTable,
+    psqt::IncrementalEval,
+    trace::NoTrace,
+};
 
 use super::{
     bitboard::{self, Bitboard},
@@ -14,6 +23,12 @@ pub struct Board {
     active_color: Color,
     info: Vec<BoardInfo>,
     evalinfo: IncrementalEval,
+    params: Arc<EvalParams>,
+    nnue: Option<Nnue>,
+    // where the kings and castling rooks start. These only differ from the e and a/h files in chess960
+    castling_kings: [Square; 2],
+    castling_rooks: [Square; 4],
+    chess960: bool, // only changes how castling moves are written
 }
 
 impl Default for Board {
@@ -25,6 +40,11 @@ impl Default for Board {
             active_color: Color::W,
             info: vec![BoardInfo::default(); 40],
             evalinfo: IncrementalEval::default(),
+            params: Arc::new(EvalParams::default()),
+            nnue: None,
+            castling_kings: [square::E1, square::E8],
+            castling_rooks: [square::H1, square::A1, square::H8, square::A8],
+            chess960: false,
         }
     }
 }
@@ -37,12 +57,34 @@ pub enum Castling {
     BQ,
 }
 
+impl Castling {
+    pub const ALL: [Castling; 4] = [Castling::WK, Castling::WQ, Castling::BK, Castling::BQ];
+
+    pub fn new(color: Color, kingside: bool) -> Self {
+        match (color, kingside) {
+            (Color::W, true) => Castling::WK,
+            (Color::W, false) => Castling::WQ,
+            (Color::B, true) => Castling::BK,
+            (Color::B, false) => Castling::BQ,
+        }
+    }
+
+    pub fn color(self) -> Color {
+        match self {
+            Castling::WK | Castling::WQ => Color::W,
+            Castling::BK | Castling::BQ => Color::B,
+        }
+    }
+}
+
 #[derive(Default, Copy, Clone, Debug)]
 pub struct BoardInfo {
     passant_square: Option<Square>,
     captured_piece: Piece,
     halfmove_clock: u8,
+    fullmove_number: u16,
     zobrist: Zobrist,
+    pawn_zobrist: Zobrist, // only the pawns, for the pawn hash table
     castling_rights: [bool; 4],
 }
 
@@ -58,16 +100,56 @@ impl Board {
     }
 
     #[inline]
-    pub fn evaluate(&self) -> i16 {
+    pub fn evaluate(&self, pawn_table: &mut PawnTable) -> i16 {
         const MULTIPLIERS: [i16; 2] = [1, -1];
-        let eval = self.evalinfo.evaluate();
-        eval * MULTIPLIERS[self.active_color() as usize]
+        let multiplier = MULTIPLIERS[self.active_color() as usize];
+        let scale = match self.endgame() {
+            Some(Endgame::Exact(eval)) => return eval * multiplier,
+            Some(Endgame::Scaled(scale)) => scale,
+            None => SCALE_NORMAL,
+        };
+        // the network has no separate endgame score to scale
+        if let Some(nnue) = &self.nnue {
+            return nnue.evaluate(self.active_color());
+        }
+        let mut score =
+            self.evalinfo.score() + pawn_table.probe(self) + self.piece_activity(&mut NoTrace);
+        score.eg = (score.eg as i32 * scale as i32 / SCALE_NORMAL as i32) as i16;
+        let eval = self.evalinfo.taper(score);
+        eval * multiplier
     }
 
     pub fn set_evalinfo(&mut self) {
         self.evalinfo = self.generate_eval();
     }
 
+    #[inline]
+    pub fn params(&self) -> &Arc<EvalParams> {
+        &self.params
+    }
+
+    pub fn set_params(&mut self, params: Arc<EvalParams>) {
+        self.params = params;
+        self.set_evalinfo();
+    }
+
+    // with no network, the board falls back to evaluating with the psqts
+    pub fn set_network(&mut self, network: Option<Arc<Network>>) {
+        self.nnue = network.map(|network| Nnue::new(network, self));
+    }
+
+    pub fn network(&self) -> Option<Arc<Network>> {
+        self.nnue.as_ref().map(Nnue::network)
+    }
+
+    pub fn set_chess960(&mut self, chess960: bool) {
+        self.chess960 = chess960;
+    }
+
+    pub fn is_chess960(&self) -> bool {
+        self.chess960
+    }
+
     pub fn is_draw(&self) -> bool {
         // material draw
         let can_force_mate = self.piece_bbs[Piece::P as usize] > 0
@@ -136,10 +218,15 @@ impl Board {
     }
 
     #[inline]
-    pub(super) fn halfmove_clock(&self) -> u8 {
+    pub fn halfmove_clock(&self) -> u8 {
         self.current_info().halfmove_clock
     }
 
+    #[inline]
+    pub fn fullmove_number(&self) -> u16 {
+        self.current_info().fullmove_number
+    }
+
     #[inline]
     pub(super) fn passant_square(&self) -> Option<Square> {
         self.current_info().passant_square
@@ -155,6 +242,26 @@ impl Board {
         &self.current_info().castling_rights[castling as usize]
     }
 
+    #[inline]
+    pub(super) fn castling_king(&self, color: Color) -> Square {
+        self.castling_kings[color as usize]
+    }
+
+    #[inline]
+    pub(super) fn castling_rook(&self, castling: Castling) -> Square {
+        self.castling_rooks[castling as usize]
+    }
+
+    pub(super) fn set_castling_squares(&mut self, castling: Castling, king: Square, rook: Square) {
+        self.castling_kings[castling.color() as usize] = king;
+        self.castling_rooks[castling as usize] = rook;
+    }
+
+    #[inline]
+    pub fn has_castling_rights(&self) -> bool {
+        self.current_info().castling_rights.contains(&true)
+    }
+
     #[inline]
     pub(super) fn castling_mut(&mut self, castling: Castling) -> &mut bool {
         &mut self.current_info_mut().castling_rights[castling as usize]
@@ -165,6 +272,11 @@ impl Board {
         self.current_info().zobrist
     }
 
+    #[inline]
+    pub fn pawn_zobrist(&self) -> Zobrist {
+        self.current_info().pawn_zobrist
+    }
+
     #[inline]
     pub(super) fn zobrist_mut(&mut self) -> &mut Zobrist {
         &mut self.current_info_mut().zobrist
@@ -221,9 +333,16 @@ impl Board {
         bitboard::set_bit(&mut self.colors[color as usize], square);
         self.piece_array[square as usize] = piece;
         if CHANGE_ZOBRIST {
-            *self.zobrist_mut() ^= zobrist::psqt_zobrist(piece, square, color);
+            let piece_zobrist = zobrist::psqt_zobrist(piece, square, color);
+            *self.zobrist_mut() ^= piece_zobrist;
+            if piece == Piece::P {
+                self.current_info_mut().pawn_zobrist ^= piece_zobrist;
+            }
+        }
+        self.evalinfo.set_piece(&self.params, square, piece, color);
+        if let Some(nnue) = &mut self.nnue {
+            nnue.set_piece(square, piece, color)
         }
-        self.evalinfo.set_piece(square, piece, color)
     }
 
     #[inline]
@@ -237,13 +356,27 @@ impl Board {
         bitboard::clear_bit(&mut self.colors[color as usize], square);
         self.piece_array[square as usize] = Piece::None;
         if CHANGE_ZOBRIST {
-            *self.zobrist_mut() ^= zobrist::psqt_zobrist(piece, square, color);
+            let piece_zobrist = zobrist::psqt_zobrist(piece, square, color);
+            *self.zobrist_mut() ^= piece_zobrist;
+            if piece == Piece::P {
+                self.current_info_mut().pawn_zobrist ^= piece_zobrist;
+            }
+        }
+        self.evalinfo
+            .remove_piece(&self.params, square, piece, color);
+        if let Some(nnue) = &mut self.nnue {
+            nnue.remove_piece(square, piece, color)
         }
-        self.evalinfo.remove_piece(square, piece, color)
     }
 
     #[inline]
-    pub(super) fn move_piece<const CHANGE_ZOBRIST: bool>(&mut self, from: Square, to: Square, piece: Piece, color: Color) {
+    pub(super) fn move_piece<const CHANGE_ZOBRIST: bool>(
+        &mut self,
+        from: Square,
+        to: Square,
+        piece: Piece,
+        color: Color,
+    ) {
         self.remove_piece::<CHANGE_ZOBRIST>(from, piece, color);
         self.add_piece::<CHANGE_ZOBRIST>(to, piece, color)
     }
@@ -278,6 +411,19 @@ impl Board {
         self.set_fifty(self.halfmove_clock() + 1)
     }
 
+    #[inline]
+    pub(super) fn set_fullmove_number(&mut self, value: u16) {
+        self.current_info_mut().fullmove_number = value;
+    }
+
+    // the fullmove number goes up once black has moved
+    #[inline]
+    pub(super) fn increment_fullmove(&mut self) {
+        if self.active_color() == Color::B {
+            self.set_fullmove_number(self.fullmove_number() + 1)
+        }
+    }
+
     #[inline]
     fn current_ep_zob(&self) -> Zobrist {
         match self.passant_square() {
@@ -299,39 +445,17 @@ impl Board {
         self.current_info_mut().passant_square = None
     }
 
+    // castling rights are lost as soon as the king or the castling rook leaves its starting square
     #[inline]
     pub(super) fn update_castle(&mut self) {
-        let white_rooks = self.piece_bb(Piece::R, Color::W);
-        let black_rooks = self.piece_bb(Piece::R, Color::B);
-
-        let wk_rook = bitboard::is_set(white_rooks, square::H1 as Square);
-        let wq_rook = bitboard::is_set(white_rooks, square::A1 as Square);
-
-        let bk_rook = bitboard::is_set(black_rooks, square::H8 as Square);
-        let bq_rook = bitboard::is_set(black_rooks, square::A8 as Square);
-
-        let white_king = self.piece_bb(Piece::K, Color::W);
-        let black_king = self.piece_bb(Piece::K, Color::B);
-
-        let w_king = bitboard::is_set(white_king, square::E1 as Square);
-        let b_king = bitboard::is_set(black_king, square::E8 as Square);
-
-        self.set_castling(
-            Castling::WK,
-            *self.castling(Castling::WK) && w_king && wk_rook,
-        );
-        self.set_castling(
-            Castling::WQ,
-            *self.castling(Castling::WQ) && w_king && wq_rook,
-        );
-        self.set_castling(
-            Castling::BK,
-            *self.castling(Castling::BK) && b_king && bk_rook,
-        );
-        self.set_castling(
-            Castling::BQ,
-            *self.castling(Castling::BQ) && b_king && bq_rook,
-        );
+        for castling in Castling::ALL {
+            let color = castling.color();
+            let king_home =
+                bitboard::is_set(self.piece_bb(Piece::K, color), self.castling_king(color));
+            let rook_home =
+                bitboard::is_set(self.piece_bb(Piece::R, color), self.castling_rook(castling));
+            self.set_castling(castling, *self.castling(castling) && king_home && rook_home);
+        }
     }
 }
 
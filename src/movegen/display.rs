@@ -69,6 +69,8 @@ impl fmt::Display for Board {
 
         writeln!(f, "|[    En Passant Square: {ep_str}")?;
         writeln!(f, "|[    Halfmove Clock: {}", self.halfmove_clock())?;
+        writeln!(f, "|[    Fullmove Number: {}", self.fullmove_number())?;
+        writeln!(f, "|[    Fen: {}", self.to_fen())?;
         writeln!(f, "|[    Zobrist Key: {}", self.zobrist())?;
         f.write_str("|[====---------------------====]|")?;
         Ok(())
@@ -1,11 +1,24 @@
-use crate::movegen::types::{square, Color, Piece};
+use std::fmt::Write;
 
-use super::board::{Board, Castling};
+use crate::movegen::types::{square, Color, Piece, Square};
+
+use super::{
+    bitboard,
+    board::{Board, Castling},
+};
 use anyhow::{bail, Context, Result};
 
 impl Board {
     pub fn parse_fen(&mut self, fen: &str) -> Result<()> {
+        // the evaluation and the chess960 mode outlive the position. Accumulators are rebuilt as pieces are
+        // placed.
+        let params = self.params().clone();
+        let network = self.network();
+        let chess960 = self.is_chess960();
         *self = Self::default();
+        self.set_params(params);
+        self.set_network(network);
+        self.set_chess960(chess960);
         let mut split_fen = fen.split_whitespace();
         let mut square = 56;
         let piece_placement_str = split_fen.next().context("Invalid fen")?;
@@ -47,16 +60,12 @@ impl Board {
         } // if it is black, swap sides. otherwise, it had better be white
 
         let castling_str = split_fen.next().context("Invalid fen")?;
-         
+
         for character in castling_str.chars() {
-            match character {
-                'K' => self.set_castling(Castling::WK, true),
-                'Q' => self.set_castling(Castling::WQ, true),
-                'k' => self.set_castling(Castling::BK, true),
-                'q' => self.set_castling(Castling::BQ, true),
-                '-' => break,
-                _ => bail!("Invalid fen"),
+            if character == '-' {
+                break;
             }
+            self.add_castling(character)?;
         }
 
         let passant_str = split_fen.next().context("Invalid fen")?;
@@ -67,7 +76,348 @@ impl Board {
 
         let fifty_str = split_fen.next().context("Invalid fen")?;
         self.set_fifty(fifty_str.parse::<u8>()?);
+
+        // the fullmove number is sometimes left off
+        let fullmove_str = split_fen.next().unwrap_or("1");
+        self.set_fullmove_number(fullmove_str.parse::<u16>()?.max(1));
         self.set_evalinfo();
         Ok(())
     }
+
+    pub fn to_fen(&self) -> String {
+        let mut fen = String::new();
+        for rank in (0..8).rev() {
+            let mut empty_squares = 0;
+            for file in 0..8 {
+                let square = square::new_sq(rank, file);
+                let piece = self.get_piece(square);
+                if piece == Piece::None {
+                    empty_squares += 1;
+                    continue;
+                }
+                if empty_squares > 0 {
+                    write!(fen, "{empty_squares}").unwrap();
+                    empty_squares = 0;
+                }
+                if self.is_color(square, Color::W) {
+                    fen.push(piece.name().to_ascii_uppercase());
+                } else {
+                    fen.push(piece.name());
+                }
+            }
+            if empty_squares > 0 {
+                write!(fen, "{empty_squares}").unwrap();
+            }
+            if rank > 0 {
+                fen.push('/');
+            }
+        }
+
+        let to_move = match self.active_color() {
+            Color::W => 'w',
+            Color::B => 'b',
+        };
+        let castling = Castling::ALL
+            .into_iter()
+            .filter(|&castling| *self.castling(castling))
+            .map(|castling| self.castling_char(castling))
+            .collect::<String>();
+        let passant = match self.passant_square() {
+            Some(square) => square::name(square),
+            None => "-",
+        };
+        write!(
+            fen,
+            " {to_move} {} {passant} {} {}",
+            if castling.is_empty() { "-" } else { &castling },
+            self.halfmove_clock(),
+            self.fullmove_number()
+        )
+        .unwrap();
+        fen
+    }
+
+    // KQkq as long as it names the outermost rook on that side, and the rook's file otherwise
+    fn castling_char(&self, castling: Castling) -> char {
+        let color = castling.color();
+        let king_square = self.castling_king(color);
+        let rook_square = self.castling_rook(castling);
+        let rooks = self.piece_bb(Piece::R, color);
+        let rank = square::rank_of(rook_square);
+        let kingside = rook_square > king_square;
+
+        let outermost = !(square::new_sq(rank, 0)..=square::new_sq(rank, 7)).any(|square| {
+            let outside = if kingside {
+                square > rook_square
+            } else {
+                square < rook_square
+            };
+            outside && bitboard::is_set(rooks, square)
+        });
+        let character = match (outermost, kingside) {
+            (true, true) => 'k',
+            (true, false) => 'q',
+            (false, _) => (b'a' + square::file_of(rook_square)) as char,
+        };
+        match color {
+            Color::W => character.to_ascii_uppercase(),
+            Color::B => character,
+        }
+    }
+
+    // Besides KQkq, castling can be given as the file of the castling rook (Shredder-FEN), which is needed in
+    // chess960. KQkq stands for the outermost rook on that side of the king (X-FEN).
+    fn add_castling(&mut self, character: char) -> Result<()> {
+        let color = if character.is_ascii_uppercase() {
+            Color::W
+        } else {
+            Color::B
+        };
+        let back_rank = match color {
+            Color::W => 0,
+            Color::B => 7,
+        };
+        let king = self.piece_bb(Piece::K, color);
+        if king.count_ones() != 1 || square::rank_of(king.trailing_zeros() as Square) != back_rank {
+            bail!("Invalid fen")
+        }
+        let king_square = king.trailing_zeros() as Square;
+        let rooks = self.piece_bb(Piece::R, color);
+        let back_rank_squares = square::new_sq(back_rank, 0)..=square::new_sq(back_rank, 7);
+
+        let rook_square = match character.to_ascii_lowercase() {
+            'k' => back_rank_squares
+                .rev()
+                .find(|&square| square > king_square && bitboard::is_set(rooks, square)),
+            'q' => back_rank_squares
+                .into_iter()
+                .find(|&square| square < king_square && bitboard::is_set(rooks, square)),
+            file @ 'a'..='h' => Some(square::new_sq(back_rank, file as u8 - b'a'))
+                .filter(|&square| square != king_square && bitboard::is_set(rooks, square)),
+            _ => None,
+        }
+        .context("Invalid fen")?;
+
+        let castling = Castling::new(color, rook_square > king_square);
+        self.set_castling_squares(castling, king_square, rook_square);
+        self.set_castling(castling, true);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::movegen::{
+        action::{Action, MoveType},
+        board::{Board, Castling},
+        types::square,
+    };
+
+    fn board(fen: &str) -> Board {
+        let mut board = Board::new();
+        board.parse_fen(fen).unwrap();
+        board
+    }
+
+    fn castling_rooks(board: &Board) -> Vec<Option<u8>> {
+        Castling::ALL
+            .into_iter()
+            .map(|castling| {
+                board
+                    .castling(castling)
+                    .then(|| board.castling_rook(castling))
+            })
+            .collect()
+    }
+
+    // parses the fen, checks it is written back as expected, and that the written fen gives the same rights
+    fn round_trip(fen: &str, expected: &str) {
+        let board = board(fen);
+        assert_eq!(board.to_fen(), expected, "{fen}");
+        let reparsed = self::board(expected);
+        assert_eq!(reparsed.to_fen(), expected, "{fen}");
+        assert_eq!(castling_rooks(&reparsed), castling_rooks(&board), "{fen}");
+    }
+
+    #[test]
+    fn shredder_fen() {
+        // outermost rooks are written as KQkq
+        round_trip(
+            "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+            "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w KQkq - 2 9",
+        );
+        round_trip(
+            "1rqbkrbn/1ppppp1p/1n6/p1N3p1/8/2P4P/PP1PPPP1/1RQBKRBN w FBfb - 0 9",
+            "1rqbkrbn/1ppppp1p/1n6/p1N3p1/8/2P4P/PP1PPPP1/1RQBKRBN w KQkq - 0 9",
+        );
+        let board = board("1rqbkrbn/1ppppp1p/1n6/p1N3p1/8/2P4P/PP1PPPP1/1RQBKRBN w FBfb - 0 9");
+        assert_eq!(
+            castling_rooks(&board),
+            [
+                Some(square::F1),
+                Some(square::B1),
+                Some(square::F8),
+                Some(square::B8)
+            ]
+        );
+    }
+
+    #[test]
+    fn x_fen() {
+        round_trip(
+            "qbbnnrkr/2pp2pp/p7/1p2pp2/8/P3PP2/1PPP1KPP/QBBNNR1R w kq - 0 9",
+            "qbbnnrkr/2pp2pp/p7/1p2pp2/8/P3PP2/1PPP1KPP/QBBNNR1R w kq - 0 9",
+        );
+        let board = board("qbbnnrkr/2pp2pp/p7/1p2pp2/8/P3PP2/1PPP1KPP/QBBNNR1R w kq - 0 9");
+        assert_eq!(
+            castling_rooks(&board),
+            [None, None, Some(square::H8), Some(square::F8)]
+        );
+    }
+
+    #[test]
+    fn inner_and_outer_rook() {
+        // two rooks on the kingside: K is the outer one, the inner one needs its file
+        let outer = board("4k3/8/8/8/8/8/8/4K1RR w K - 0 1");
+        assert_eq!(outer.castling_rook(Castling::WK), square::H1);
+        round_trip(
+            "4k3/8/8/8/8/8/8/4K1RR w H - 0 1",
+            "4k3/8/8/8/8/8/8/4K1RR w K - 0 1",
+        );
+        round_trip(
+            "4k3/8/8/8/8/8/8/4K1RR w G - 0 1",
+            "4k3/8/8/8/8/8/8/4K1RR w G - 0 1",
+        );
+
+        // the same on the queenside, for black
+        let outer = board("rr2k3/8/8/8/8/8/8/4K3 b q - 0 1");
+        assert_eq!(outer.castling_rook(Castling::BQ), square::A8);
+        round_trip(
+            "rr2k3/8/8/8/8/8/8/4K3 b a - 0 1",
+            "rr2k3/8/8/8/8/8/8/4K3 b q - 0 1",
+        );
+        round_trip(
+            "rr2k3/8/8/8/8/8/8/4K3 b b - 0 1",
+            "rr2k3/8/8/8/8/8/8/4K3 b b - 0 1",
+        );
+
+        // an inner rook on one side and an outer rook on the other
+        round_trip(
+            "rr2k2r/8/8/8/8/8/8/R3K1RR w GAhb - 0 1",
+            "rr2k2r/8/8/8/8/8/8/R3K1RR w GQkb - 0 1",
+        );
+    }
+
+    #[test]
+    fn invalid_castling() {
+        let mut board = Board::new();
+        // no rook on the named file
+        assert!(board.parse_fen("4k3/8/8/8/8/8/8/4K2R w A - 0 1").is_err());
+        // no rook on that side of the king
+        assert!(board.parse_fen("4k3/8/8/8/8/8/8/4K2R w Q - 0 1").is_err());
+        // the king isn't on the back rank
+        assert!(board.parse_fen("4k3/8/8/8/8/8/4K3/7R w K - 0 1").is_err());
+    }
+
+    // castling is encoded as the king capturing its own rook
+    fn castle(fen: &str, from: u8, to: u8, expected: &str) {
+        let mut board = board(fen);
+        let action = Action::new_type(from, to, MoveType::Castle);
+        let mut movelist = crate::movegen::movelist::MoveList::new();
+        board.genmoves::<{ crate::movegen::genmoves::GenType::ALL }>(&mut movelist);
+        assert!(movelist.iter().any(|&m| *m == action), "{fen}");
+
+        board.make_move(action);
+        assert_eq!(board.to_fen(), expected, "{fen}");
+        board.unmake_move(action);
+        assert_eq!(board.to_fen(), fen, "{fen}");
+    }
+
+    #[test]
+    fn king_takes_rook() {
+        castle(
+            "4k3/8/8/8/8/8/8/4K1RR w G - 0 1",
+            square::E1,
+            square::G1,
+            "4k3/8/8/8/8/8/8/5RKR b - - 1 1",
+        );
+        // the king doesn't move
+        castle(
+            "4k3/8/8/8/8/8/8/6KR w K - 0 1",
+            square::G1,
+            square::H1,
+            "4k3/8/8/8/8/8/8/5RK1 b - - 1 1",
+        );
+        // king and rook swap squares
+        castle(
+            "4k3/8/8/8/8/8/8/5KR1 w K - 0 1",
+            square::F1,
+            square::G1,
+            "4k3/8/8/8/8/8/8/5RK1 b - - 1 1",
+        );
+        castle(
+            "rk6/8/8/8/8/8/8/4K3 b q - 3 7",
+            square::B8,
+            square::A8,
+            "2kr4/8/8/8/8/8/8/4K3 w - - 4 8",
+        );
+    }
+
+    #[test]
+    fn round_trips() {
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b Kq - 3 17",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 b - - 42 93",
+        ] {
+            assert_eq!(board(fen).to_fen(), fen);
+        }
+    }
+
+    #[test]
+    fn make_and_unmake() {
+        let mut board = Board::new();
+        board
+            .parse_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1")
+            .unwrap();
+        // the fen after each move, with the halfmove clock reset by pawn moves and captures and the fullmove
+        // number going up after black moves
+        let moves = [
+            (
+                "e2e4",
+                "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1",
+            ),
+            (
+                "g8f6",
+                "rnbqkb1r/pppppppp/5n2/8/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 1 2",
+            ),
+            (
+                "g1f3",
+                "rnbqkb1r/pppppppp/5n2/8/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 2 2",
+            ),
+            (
+                "f6e4",
+                "rnbqkb1r/pppppppp/8/8/4n3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 0 3",
+            ),
+            (
+                "h1g1",
+                "rnbqkb1r/pppppppp/8/8/4n3/5N2/PPPP1PPP/RNBQKBR1 b Qkq - 1 3",
+            ),
+        ];
+        let mut history = vec![(board.to_fen(), None)];
+        for (movestring, fen) in moves {
+            let action = board.parse_move(movestring).unwrap();
+            board.make_move(action);
+            assert_eq!(board.to_fen(), fen, "{movestring}");
+            history.push((board.to_fen(), Some(action)));
+        }
+        while let Some((fen, action)) = history.pop() {
+            assert_eq!(board.to_fen(), fen);
+            if let Some(action) = action {
+                board.unmake_move(action);
+            }
+        }
+    }
 }
@@ -1,5 +1,5 @@
 use super::{
-    action::{Action, MoveType},
+    action::{castling_destinations, Action, MoveType},
     atks,
     bitboard::{self, Bitboard, Direction},
     board::{Board, Castling},
@@ -334,43 +334,34 @@ impl Board {
 
     fn generate_castles(&self, atk_mask: Bitboard, movelist: &mut MoveList) {
         let us = self.active_color();
-        let occ = self.occupancy();
+        let king_from = self.castling_king(us);
         // we are not in check, so generate castling
-        let kingside_rights = select!(us, self.castling(Castling::WK), self.castling(Castling::BK));
-        let queenside_rights =
-            select!(us, self.castling(Castling::WQ), self.castling(Castling::BQ));
-        // TODO
-        if *kingside_rights {
-            const BLOCKED_KINGSIDE_W: Bitboard = new_bbs!(square::G1, square::F1);
-            const BLOCKED_KINGSIDE_B: Bitboard = new_bbs!(square::G8, square::F8);
-            let block_bb = select!(us, BLOCKED_KINGSIDE_W, BLOCKED_KINGSIDE_B);
-
-            // checks if castling is obstructed by pieces or check
-            let is_safe = block_bb & occ == 0 && block_bb & atk_mask == 0;
-            if is_safe {
-                let king_from = select!(us, square::E1, square::E8);
-                let king_to = select!(us, square::G1, square::G8);
-                movelist.push(Action::new_type(king_from, king_to, MoveType::Castle).into())
+        for kingside in [true, false] {
+            let castling = Castling::new(us, kingside);
+            if !*self.castling(castling) {
+                continue;
             }
-        }
-
-        if *queenside_rights {
-            const CHECKED_QUEENSIDE_W: Bitboard = new_bbs!(square::D1, square::C1);
-            const CHECKED_QUEENSIDE_B: Bitboard = new_bbs!(square::D8, square::C8);
-            const BLOCKED_QUEENSIDE_W: Bitboard = new_bbs!(square::D1, square::C1, square::B1);
-            const BLOCKED_QUEENSIDE_B: Bitboard = new_bbs!(square::D8, square::C8, square::B8);
-
-            let block_bb = select!(us, BLOCKED_QUEENSIDE_W, BLOCKED_QUEENSIDE_B);
-            let check_bb = select!(us, CHECKED_QUEENSIDE_W, CHECKED_QUEENSIDE_B);
-            // checks if castling is obstructed by pieces or check
-            let is_safe = block_bb & occ == 0 && check_bb & atk_mask == 0;
+            let rook_from = self.castling_rook(castling);
+            let (king_to, rook_to) = castling_destinations(king_from, rook_from);
+
+            // every square either piece passes over has to be empty, apart from the two castling pieces
+            let king_path = atks::in_btwn_atks(king_from, king_to) | bitboard::new_bb(king_to);
+            let rook_path = atks::in_btwn_atks(rook_from, rook_to) | bitboard::new_bb(rook_to);
+            let others = self.occupancy() & !new_bbs!(king_from, rook_from);
+
+            // checks if castling is obstructed by pieces or check. In chess960 the castling rook can be the
+            // only thing between its king's destination and an enemy rook, which the attack mask doesn't see
+            let is_safe = (king_path | rook_path) & others == 0
+                && king_path & atk_mask == 0
+                && atks::rook_attacks(king_to, others | bitboard::new_bb(king_from))
+                    & self.orthogonal_sliders(!us)
+                    == 0;
             if is_safe {
-                let king_from = select!(us, square::E1, square::E8);
-                let king_to = select!(us, square::C1, square::C8);
-                movelist.push(Action::new_type(king_from, king_to, MoveType::Castle).into())
+                movelist.push(Action::new_type(king_from, rook_from, MoveType::Castle).into())
             }
         }
     }
+
     fn generate_passant(&mut self, movelist: &mut MoveList) {
         let us = self.active_color();
         let them = !us;
@@ -7,5 +7,6 @@ pub mod fen;
 pub mod genmoves;
 pub mod movelist;
 pub mod perft;
+pub mod san;
 pub mod types;
 pub mod zobrist;
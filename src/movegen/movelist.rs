@@ -37,6 +37,10 @@ impl<T, const N: usize> List<T, N> {
         self.length
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
     pub fn swap(&mut self, idx1: usize, idx2: usize) {
         self.items.swap(idx1, idx2);
     }
@@ -1,6 +1,6 @@
 use crate::movegen::genmoves::GenType;
 
-use super::{board::Board, movelist::MoveList, zobrist::Zobrist};
+use super::{action::Action, board::Board, movelist::MoveList, zobrist::Zobrist};
 
 #[allow(dead_code)]
 pub fn perft_debug() {
@@ -22,7 +22,14 @@ pub fn perft_debug() {
         board.parse_moves(moves);
     }
 
-    board.divide_perft(depth);
+    print_divide(&board.divide_perft(depth), board.is_chess960());
+}
+
+pub fn print_divide(divide: &[(Action, u64)], chess960: bool) {
+    for (action, nodes) in divide {
+        println!("{} {nodes}", action.to_uci(chess960));
+    }
+    println!("\n{}", divide.iter().map(|(_, nodes)| nodes).sum::<u64>());
 }
 
 #[derive(Clone, Copy, Default)]
@@ -33,40 +40,39 @@ struct PerftEntry {
 }
 
 impl Board {
-    pub fn divide_perft(&mut self, depth: u8) {
+    // the nodes under each root move
+    pub fn divide_perft(&mut self, depth: u8) -> Vec<(Action, u64)> {
         let mut start_list = MoveList::new();
         self.genmoves::<{ GenType::ALL }>(&mut start_list);
-        let mut total_nodes = 0;
+        let mut divide = Vec::new();
 
         for action in start_list.iter() {
             self.make_move(**action);
 
             let perft = self.perft(depth - 1);
             self.unmake_move(**action);
-            total_nodes += perft;
-            println!("{} {perft}", **action);
+            divide.push((**action, perft));
         }
-        println!("\n{}", total_nodes);
+        divide
     }
 
-    pub fn hashed_divide_perft(&mut self, depth: u8, hash_size: usize) {
+    pub fn hashed_divide_perft(&mut self, depth: u8, hash_size: usize) -> Vec<(Action, u64)> {
         let mut hashtable = vec![PerftEntry::default(); hash_size];
         let mut start_list = MoveList::new();
         self.genmoves::<{ GenType::ALL }>(&mut start_list);
-        let mut total_nodes = 0;
+        let mut divide = Vec::new();
 
         for action in start_list.iter() {
             self.make_move(**action);
 
             let perft = self.hashed_perft(depth - 1, &mut hashtable);
             self.unmake_move(**action);
-            total_nodes += perft;
-            println!("{} {perft}", **action);
+            divide.push((**action, perft));
         }
-        println!("\n{}", total_nodes);
+        divide
     }
 
-    fn hashed_perft(&mut self, depth: u8, hashtable: &mut Vec<PerftEntry>) -> u64 {
+    fn hashed_perft(&mut self, depth: u8, hashtable: &mut [PerftEntry]) -> u64 {
         if depth == 0 {
             return 1;
         }
@@ -94,7 +100,7 @@ impl Board {
             nodes
         }
     }
-    fn perft(&mut self, depth: u8) -> u64 {
+    pub fn perft(&mut self, depth: u8) -> u64 {
         if depth == 0 {
             return 1;
         }
@@ -115,3 +121,171 @@ impl Board {
         nodes
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::movegen::board::Board;
+
+    // the fast tier stops at the first depth with more nodes than this, since tests run unoptimized
+    const FAST_NODE_LIMIT: u64 = 100_000;
+
+    // node counts from depth 1 onwards
+    type Position = (&'static str, &'static [u64]);
+
+    const STANDARD: [Position; 6] = [
+        // startpos
+        (
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            &[20, 400, 8902, 197281, 4865609],
+        ),
+        // kiwipete
+        (
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            &[48, 2039, 97862, 4085603],
+        ),
+        (
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            &[14, 191, 2812, 43238, 674624, 11030083],
+        ),
+        (
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            &[6, 264, 9467, 422333, 15833292],
+        ),
+        (
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            &[44, 1486, 62379, 2103487],
+        ),
+        (
+            "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+            &[46, 2079, 89890, 3894594],
+        ),
+    ];
+
+    // each case comes with its mirror image, with the colors swapped
+    const EDGE_CASES: [Position; 30] = [
+        // en passant would leave the king in check
+        ("3k4/3p4/8/K1P4r/8/8/8/8 b - - 0 1", &[18, 92, 1670, 10138, 185429, 1134888]),
+        ("8/8/8/8/k1p4R/8/3P4/3K4 w - - 0 1", &[18, 92, 1670, 10138, 185429, 1134888]),
+        ("8/8/4k3/8/2p5/8/B2P2K1/8 w - - 0 1", &[13, 102, 1266, 10276, 135655, 1015133]),
+        ("8/b2p2k1/8/2P5/8/4K3/8/8 b - - 0 1", &[13, 102, 1266, 10276, 135655, 1015133]),
+        // en passant capture gives check
+        ("8/8/1k6/2b5/2pP4/8/5K2/8 b - d3 0 1", &[15, 126, 1928, 13931, 206379, 1440467]),
+        ("8/5k2/8/2Pp4/2B5/1K6/8/8 w - d6 0 1", &[15, 126, 1928, 13931, 206379, 1440467]),
+        // castling gives check
+        ("5k2/8/8/8/8/8/8/4K2R w K - 0 1", &[15, 66, 1198, 6399, 120330, 661072]),
+        ("4k2r/8/8/8/8/8/8/5K2 b k - 0 1", &[15, 66, 1198, 6399, 120330, 661072]),
+        ("3k4/8/8/8/8/8/8/R3K3 w Q - 0 1", &[16, 71, 1286, 7418, 141077, 803711]),
+        ("r3k3/8/8/8/8/8/8/3K4 b q - 0 1", &[16, 71, 1286, 7418, 141077, 803711]),
+        // castling rights lost to rook captures
+        ("r3k2r/1b4bq/8/8/8/8/7B/R3K2R w KQkq - 0 1", &[26, 1141, 27826, 1274206]),
+        ("r3k2r/7b/8/8/8/8/1B4BQ/R3K2R b KQkq - 0 1", &[26, 1141, 27826, 1274206]),
+        // castling through attacked squares
+        ("r3k2r/8/3Q4/8/8/5q2/8/R3K2R b KQkq - 0 1", &[44, 1494, 50509, 1720476]),
+        ("r3k2r/8/5Q2/8/8/3q4/8/R3K2R w KQkq - 0 1", &[44, 1494, 50509, 1720476]),
+        // promoting out of check
+        ("2K2r2/4P3/8/8/8/8/8/3k4 w - - 0 1", &[11, 133, 1442, 19174, 266199, 3821001]),
+        ("3K4/8/8/8/8/8/4p3/2k2R2 b - - 0 1", &[11, 133, 1442, 19174, 266199, 3821001]),
+        // discovered check
+        ("8/8/1P2K3/8/2n5/1q6/8/5k2 b - - 0 1", &[29, 165, 5160, 31961, 1004658]),
+        ("5K2/8/1Q6/2N5/8/1p2k3/8/8 w - - 0 1", &[29, 165, 5160, 31961, 1004658]),
+        // promoting to give check
+        ("4k3/1P6/8/8/8/8/K7/8 w - - 0 1", &[9, 40, 472, 2661, 38983, 217342]),
+        ("8/k7/8/8/8/8/1p6/4K3 b - - 0 1", &[9, 40, 472, 2661, 38983, 217342]),
+        // underpromoting to give check
+        ("8/P1k5/K7/8/8/8/8/8 w - - 0 1", &[6, 27, 273, 1329, 18135, 92683]),
+        ("8/8/8/8/8/k7/p1K5/8 b - - 0 1", &[6, 27, 273, 1329, 18135, 92683]),
+        // stalemating yourself
+        ("K1k5/8/P7/8/8/8/8/8 w - - 0 1", &[2, 6, 13, 63, 382, 2217]),
+        ("8/8/8/8/8/p7/8/k1K5 b - - 0 1", &[2, 6, 13, 63, 382, 2217]),
+        // stalemate and checkmate
+        ("8/k1P5/8/1K6/8/8/8/8 w - - 0 1", &[10, 25, 268, 926, 10857, 43261, 567584]),
+        ("8/8/8/8/1k6/8/K1p5/8 b - - 0 1", &[10, 25, 268, 926, 10857, 43261, 567584]),
+        // double check
+        ("8/8/2k5/5q2/5n2/8/5K2/8 b - - 0 1", &[37, 183, 6559, 23527]),
+        ("8/5k2/8/5N2/5Q2/2K5/8/8 w - - 0 1", &[37, 183, 6559, 23527]),
+        // a pawn pinned along the rank can't capture en passant
+        ("8/8/8/K2pP2r/8/8/8/7k w - d6 0 1", &[6, 78, 528, 8288, 55203, 921406]),
+        ("7K/8/8/8/k2Pp2R/8/8/8 b - d3 0 1", &[6, 78, 528, 8288, 55203, 921406]),
+    ];
+
+    const CHESS960: [Position; 5] = [
+        (
+            "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+            &[21, 528, 12189, 326672, 8146062],
+        ),
+        (
+            "2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9",
+            &[21, 807, 18002, 667366, 16253601],
+        ),
+        (
+            "b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9",
+            &[20, 479, 10471, 273318],
+        ),
+        (
+            "qbbnnrkr/2pp2pp/p7/1p2pp2/8/P3PP2/1PPP1KPP/QBBNNR1R w hf - 0 9",
+            &[22, 593, 13440, 382958, 9183776],
+        ),
+        (
+            "1rqbkrbn/1ppppp1p/1n6/p1N3p1/8/2P4P/PP1PPPP1/1RQBKRBN w FBfb - 0 9",
+            &[29, 502, 14569, 287739],
+        ),
+    ];
+
+    fn check(positions: &[Position], node_limit: u64) {
+        for &(fen, counts) in positions {
+            let mut board = Board::new();
+            board.parse_fen(fen).unwrap();
+            let zobrist = board.zobrist();
+            for (depth, &expected) in (1..).zip(counts.iter()) {
+                if expected > node_limit {
+                    break;
+                }
+                assert_eq!(board.perft(depth), expected, "{fen} at depth {depth}");
+                // unmaking every move has to get back to the exact same position
+                assert_eq!(board.zobrist(), zobrist, "{fen} at depth {depth}");
+            }
+        }
+    }
+
+    #[test]
+    fn standard() {
+        check(&STANDARD, FAST_NODE_LIMIT)
+    }
+
+    #[test]
+    fn edge_cases() {
+        check(&EDGE_CASES, FAST_NODE_LIMIT)
+    }
+
+    #[test]
+    fn chess960() {
+        check(&CHESS960, FAST_NODE_LIMIT)
+    }
+
+    #[test]
+    fn hashed() {
+        let mut board = Board::new();
+        board.parse_fen(STANDARD[1].0).unwrap();
+        let mut hashtable = vec![super::PerftEntry::default(); 1 << 16];
+        assert_eq!(board.hashed_perft(3, &mut hashtable), STANDARD[1].1[2]);
+        assert_eq!(board.hashed_perft(3, &mut hashtable), STANDARD[1].1[2]);
+    }
+
+    #[test]
+    #[ignore = "slow, run with cargo test --release -- --ignored"]
+    fn standard_deep() {
+        check(&STANDARD, u64::MAX)
+    }
+
+    #[test]
+    #[ignore = "slow, run with cargo test --release -- --ignored"]
+    fn edge_cases_deep() {
+        check(&EDGE_CASES, u64::MAX)
+    }
+
+    #[test]
+    #[ignore = "slow, run with cargo test --release -- --ignored"]
+    fn chess960_deep() {
+        check(&CHESS960, u64::MAX)
+    }
+}
@@ -0,0 +1,376 @@
+use super::{
+    action::{Action, MoveType},
+    board::Board,
+    genmoves::GenType,
+    movelist::MoveList,
+    types::{square, Piece, Square},
+};
+
+impl Board {
+    // standard algebraic notation for a legal move, with a check or mate suffix
+    pub fn to_san(&self, action: Action) -> String {
+        // finding out about ambiguity and check means generating moves, and making the move
+        let mut board = self.clone();
+        board.write_san(action)
+    }
+
+    fn write_san(&mut self, action: Action) -> String {
+        let mut san = String::new();
+        let (from, to) = (action.from(), action.to());
+        let piece = self.get_piece(from);
+
+        if action.move_type() == MoveType::Castle {
+            san.push_str(if to > from { "O-O" } else { "O-O-O" });
+        } else {
+            let is_capture =
+                action.move_type() == MoveType::Passant || self.is_color(to, !self.active_color());
+            if piece == Piece::P {
+                if is_capture {
+                    san.push(file_char(from));
+                }
+            } else {
+                san.push(piece.name().to_ascii_uppercase());
+                san.push_str(&self.disambiguation(action));
+            }
+            if is_capture {
+                san.push('x');
+            }
+            san.push_str(square::name(to));
+            if action.move_type() == MoveType::Promotion {
+                san.push('=');
+                san.push(action.pr_piece().name().to_ascii_uppercase());
+            }
+        }
+
+        self.make_move(action);
+        if self.in_check(self.active_color()) {
+            let mut movelist = MoveList::new();
+            self.genmoves::<{ GenType::ALL }>(&mut movelist);
+            san.push(if movelist.is_empty() { '#' } else { '+' });
+        }
+        self.unmake_move(action);
+        san
+    }
+
+    // Finds the legal move written in standard algebraic notation. Check and annotation suffixes are optional,
+    // and castling with zeros, promotions without '=', and long algebraic moves are all accepted.
+    pub fn parse_san(&mut self, san: &str) -> Option<Action> {
+        let san = san
+            .trim()
+            .trim_end_matches(['+', '#', '!', '?'])
+            .trim_end_matches("e.p.")
+            .trim_end();
+        let mut movelist = MoveList::new();
+        self.genmoves::<{ GenType::ALL }>(&mut movelist);
+        let mut legal_moves = movelist.iter().map(|action| **action);
+
+        let castle_side = match san {
+            "O-O" | "0-0" => Some(true),
+            "O-O-O" | "0-0-0" => Some(false),
+            _ => None,
+        };
+        if let Some(kingside) = castle_side {
+            return legal_moves.find(|action| {
+                action.move_type() == MoveType::Castle && (action.to() > action.from()) == kingside
+            });
+        }
+
+        let mut chars = san
+            .chars()
+            .filter(|&character| !matches!(character, 'x' | ':' | '-' | '='))
+            .collect::<Vec<_>>();
+
+        let piece = match chars.first() {
+            Some(&character) if "NBRQK".contains(character) => {
+                chars.remove(0);
+                piece_from_char(character)?
+            }
+            _ => Piece::P,
+        };
+        let promotion = match chars.last() {
+            Some(&character) if character.is_ascii_alphabetic() => {
+                chars.pop();
+                Some(piece_from_char(character.to_ascii_uppercase())?)
+            }
+            _ => None,
+        };
+
+        if chars.len() < 2 {
+            return None;
+        }
+        let destination: String = chars.split_off(chars.len() - 2).into_iter().collect();
+        let to = square::from_algebraic(&destination)?;
+        // whatever is left narrows down the starting square
+        let mut from_file = None;
+        let mut from_rank = None;
+        for character in chars {
+            match character {
+                'a'..='h' => from_file = Some(character as u8 - b'a'),
+                '1'..='8' => from_rank = Some(character as u8 - b'1'),
+                _ => return None,
+            }
+        }
+
+        let mut candidates = legal_moves.filter(|action| {
+            action.move_type() != MoveType::Castle
+                && action.to() == to
+                && self.get_piece(action.from()) == piece
+                && from_file.is_none_or(|file| square::file_of(action.from()) == file)
+                && from_rank.is_none_or(|rank| square::rank_of(action.from()) == rank)
+                && match promotion {
+                    Some(promotion) => {
+                        action.move_type() == MoveType::Promotion && action.pr_piece() == promotion
+                    }
+                    None => action.move_type() != MoveType::Promotion,
+                }
+        });
+        let action = candidates.next()?;
+        // ambiguous moves aren't guessed at
+        if candidates.next().is_some() {
+            return None;
+        }
+        Some(action)
+    }
+
+    // Finds the legal move in long algebraic notation, as uci uses, with standard algebraic notation as a
+    // fallback. The move isn't made.
+    pub fn parse_move(&mut self, movestring: &str) -> Option<Action> {
+        let mut list = MoveList::new();
+        self.genmoves::<{ GenType::ALL }>(&mut list);
+        let chess960 = self.is_chess960();
+        let action = list
+            .iter()
+            .find(|&action| action.to_uci(chess960) == movestring);
+        match action {
+            Some(action) => Some(**action),
+            None => self.parse_san(movestring),
+        }
+    }
+
+    // makes each of the moves in turn, stopping at the first one that isn't legal
+    pub fn parse_moves<'a, T>(&mut self, actions: T) -> Option<()>
+    where
+        T: Iterator<Item = &'a str>,
+    {
+        for movestring in actions {
+            let action = self.parse_move(movestring)?;
+            self.make_move(action);
+        }
+        Some(())
+    }
+
+    // the file, rank or whole square of the starting square, if another piece of the same type can go there too
+    fn disambiguation(&mut self, action: Action) -> String {
+        let (from, to) = (action.from(), action.to());
+        let piece = self.get_piece(from);
+        let mut movelist = MoveList::new();
+        self.genmoves::<{ GenType::ALL }>(&mut movelist);
+
+        let others = movelist
+            .iter()
+            .map(|other| **other)
+            .filter(|other| {
+                other.move_type() != MoveType::Castle
+                    && other.to() == to
+                    && other.from() != from
+                    && self.get_piece(other.from()) == piece
+            })
+            .collect::<Vec<_>>();
+        if others.is_empty() {
+            return String::new();
+        }
+
+        let same_file = others
+            .iter()
+            .any(|other| square::file_of(other.from()) == square::file_of(from));
+        let same_rank = others
+            .iter()
+            .any(|other| square::rank_of(other.from()) == square::rank_of(from));
+        if !same_file {
+            file_char(from).to_string()
+        } else if !same_rank {
+            rank_char(from).to_string()
+        } else {
+            square::name(from).to_string()
+        }
+    }
+}
+
+fn file_char(square: Square) -> char {
+    (b'a' + square::file_of(square)) as char
+}
+
+fn rank_char(square: Square) -> char {
+    (b'1' + square::rank_of(square)) as char
+}
+
+fn piece_from_char(character: char) -> Option<Piece> {
+    [Piece::N, Piece::B, Piece::R, Piece::Q, Piece::K]
+        .into_iter()
+        .find(|piece| piece.name() == character.to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::movegen::{action::MoveType, board::Board, genmoves::GenType, movelist::MoveList};
+
+    fn board(fen: &str) -> Board {
+        let mut board = Board::new();
+        board.parse_fen(fen).unwrap();
+        board
+    }
+
+    // the san of a move given in long algebraic notation
+    fn san(fen: &str, movestring: &str) -> String {
+        let mut board = board(fen);
+        let action = board.parse_move(movestring).unwrap();
+        board.to_san(action)
+    }
+
+    #[test]
+    fn round_trip() {
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "4k3/7K/3Q4/8/3Q1Q2/8/1N3N2/R6R w - - 0 1",
+            "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+        ] {
+            let mut board = board(fen);
+            let mut movelist = MoveList::new();
+            board.genmoves::<{ GenType::ALL }>(&mut movelist);
+            for action in movelist.iter().map(|action| **action) {
+                let san = board.to_san(action);
+                assert_eq!(board.parse_san(&san), Some(action), "{san} in {fen}");
+            }
+        }
+    }
+
+    #[test]
+    fn disambiguation() {
+        let fen = "4k3/7K/3Q4/8/3Q1Q2/8/1N3N2/R6R w - - 0 1";
+        // by file
+        assert_eq!(san(fen, "a1d1"), "Rad1");
+        assert_eq!(san(fen, "b2d3"), "Nbd3");
+        // by rank
+        assert_eq!(san(fen, "d6d5"), "Q6d5");
+        // by the whole square
+        assert_eq!(san(fen, "d4f6"), "Qd4f6");
+        // no need when only one piece can get there
+        assert_eq!(san(fen, "f2g4"), "Ng4");
+
+        let mut board = board(fen);
+        assert_eq!(board.parse_san("Rad1"), board.parse_move("a1d1"));
+        assert_eq!(board.parse_san("Q6d5"), board.parse_move("d6d5"));
+        assert_eq!(board.parse_san("Qd4f6"), board.parse_move("d4f6"));
+        assert_eq!(board.parse_san("Qd4xf6"), board.parse_move("d4f6"));
+    }
+
+    #[test]
+    fn ambiguous() {
+        let mut board = board("4k3/7K/3Q4/8/3Q1Q2/8/1N3N2/R6R w - - 0 1");
+        assert_eq!(board.parse_san("Rd1"), None);
+        assert_eq!(board.parse_san("Nd3"), None);
+        assert_eq!(board.parse_san("Qf6"), None);
+        // the d file still leaves two queens
+        assert_eq!(board.parse_san("Qdf6"), None);
+        // not legal at all
+        assert_eq!(board.parse_san("Ke2"), None);
+        assert_eq!(board.parse_san("Bc4"), None);
+    }
+
+    #[test]
+    fn promotions() {
+        let fen = "3r4/4P3/8/8/8/8/8/K1k5 w - - 0 1";
+        assert_eq!(san(fen, "e7e8q"), "e8=Q");
+        assert_eq!(san(fen, "e7d8n"), "exd8=N");
+
+        let mut board = board(fen);
+        for movestring in ["e8=Q", "e8Q", "e8q"] {
+            assert_eq!(
+                board.parse_san(movestring),
+                board.parse_move("e7e8q"),
+                "{movestring}"
+            );
+        }
+        for movestring in ["exd8=N", "exd8N", "ed8N", "e7d8n"] {
+            assert_eq!(
+                board.parse_san(movestring),
+                board.parse_move("e7d8n"),
+                "{movestring}"
+            );
+        }
+        // a promotion has to say what it promotes to
+        assert_eq!(board.parse_san("e8"), None);
+    }
+
+    #[test]
+    fn castling() {
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        assert_eq!(san(fen, "e1g1"), "O-O");
+        assert_eq!(san(fen, "e1c1"), "O-O-O");
+
+        let mut board = board(fen);
+        for (movestring, kingside) in [
+            ("O-O", true),
+            ("0-0", true),
+            ("O-O-O", false),
+            ("0-0-0", false),
+        ] {
+            let action = board.parse_san(movestring).unwrap();
+            assert_eq!(action.move_type(), MoveType::Castle, "{movestring}");
+            assert_eq!(action.to() > action.from(), kingside, "{movestring}");
+        }
+        assert_eq!(board.parse_san("O-O"), board.parse_move("e1g1"));
+        assert_eq!(board.parse_san("O-O-O"), board.parse_move("e1c1"));
+    }
+
+    #[test]
+    fn check_and_mate() {
+        assert_eq!(san("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", "a1a8"), "Ra8+");
+        assert_eq!(san("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", "a1a8"), "Ra8#");
+        assert_eq!(san("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", "a1a7"), "Ra7");
+
+        let mut board = board("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+        let action = board.parse_move("a1a8");
+        for movestring in ["Ra8", "Ra8+", "Ra8#", "Ra8#!", "Ra8!?"] {
+            assert_eq!(board.parse_san(movestring), action, "{movestring}");
+        }
+    }
+
+    #[test]
+    fn en_passant() {
+        let fen = "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1";
+        assert_eq!(san(fen, "e5d6"), "exd6");
+
+        let mut board = board(fen);
+        let action = board.parse_san("exd6").unwrap();
+        assert_eq!(action.move_type(), MoveType::Passant);
+        assert_eq!(board.parse_san("exd6 e.p."), Some(action));
+        assert_eq!(board.parse_san("exd6e.p."), Some(action));
+        assert_eq!(board.parse_san("ed6"), Some(action));
+    }
+
+    #[test]
+    fn uci_castling() {
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        let mut standard = board(fen);
+        let mut chess960 = board(fen);
+        chess960.set_chess960(true);
+        // the mode is kept when the position changes
+        chess960.parse_fen(fen).unwrap();
+        assert!(chess960.is_chess960() && !standard.is_chess960());
+
+        let action = standard.parse_move("e1g1").unwrap();
+        assert_eq!(action.move_type(), MoveType::Castle);
+        assert_eq!(chess960.parse_move("e1h1"), Some(action));
+        assert_eq!(action.to_uci(false), "e1g1");
+        assert_eq!(action.to_uci(true), "e1h1");
+        assert_eq!(action.to_string(), "e1g1");
+
+        // the king taking its rook is only castling in chess960
+        assert_eq!(standard.parse_move("e1h1"), None);
+        assert_eq!(chess960.parse_move("e1c1"), None);
+    }
+}
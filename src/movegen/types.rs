@@ -92,16 +92,17 @@ pub mod square {
     }
 
     #[inline]
+    // wraps off the board, since select! works out the square for both colors before picking one
     pub(in super::super) const fn shift(square: Square, direction: Direction) -> Square {
         match direction {
-            Direction::N => square + 8,
-            Direction::S => square - 8,
-            Direction::E => square + 1,
-            Direction::W => square - 1,
-            Direction::NE => square + 9,
-            Direction::NW => square + 7,
-            Direction::SE => square - 7,
-            Direction::SW => square - 9,
+            Direction::N => square.wrapping_add(8),
+            Direction::S => square.wrapping_sub(8),
+            Direction::E => square.wrapping_add(1),
+            Direction::W => square.wrapping_sub(1),
+            Direction::NE => square.wrapping_add(9),
+            Direction::NW => square.wrapping_add(7),
+            Direction::SE => square.wrapping_sub(7),
+            Direction::SW => square.wrapping_sub(9),
         }
     }
 
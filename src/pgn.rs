@@ -0,0 +1,605 @@
+// Reading and writing games in PGN. Games are replayed on a board as they are read, so every move is checked
+// and stored as an Action. Comments, NAGs and variations are kept, and comments starting with an evaluation
+// the way engine matches write them, like {+0.35/12 0.52s}, have it parsed out.
+//
+// usage: ShenYu pgn <games> [output]
+// Replays every game in the file. If the output ends in .pgn the games are written back out, otherwise every
+// position of every finished game's main line is written out with the game's result, for the tuner.
+
+use std::{
+    fmt::{self, Write as _},
+    fs,
+    iter::Peekable,
+    vec,
+};
+
+use anyhow::{bail, Context, Result};
+
+use shenyu::movegen::{action::Action, board::Board, types::Color};
+
+const DEFAULT_OUTPUT: &str = "positions.txt";
+const LINE_LENGTH: usize = 80;
+const RESULTS: [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+
+#[derive(Clone, Debug, Default)]
+pub struct Game {
+    pub tags: Vec<(String, String)>,
+    pub line: Line,
+    pub result: String,
+}
+
+// a sequence of moves, either the main line or a variation
+#[derive(Clone, Debug, Default)]
+pub struct Line {
+    pub comment: Option<String>, // in front of the first move
+    pub moves: Vec<PgnMove>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PgnMove {
+    pub action: Action,
+    pub nags: Vec<u8>,
+    pub eval: Option<Eval>,
+    pub comment: Option<String>,
+    pub variations: Vec<Line>, // alternatives to this move
+}
+
+// from the point of view of the side that made the move
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Eval {
+    pub score: PgnScore,
+    pub depth: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PgnScore {
+    Centipawns(i32),
+    Mate(i32), // negative when getting mated
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+    Tag(String, String),
+    Comment(String),
+    Nag(u8),
+    Open,
+    Close,
+    Result(String),
+    Move(String),
+}
+
+type Tokens = Peekable<vec::IntoIter<Token>>;
+
+pub fn run(mut args: impl Iterator<Item = String>) -> Result<()> {
+    let input = args.next().context("usage: pgn <games> [output]")?;
+    let output = args.next().unwrap_or_else(|| DEFAULT_OUTPUT.to_string());
+
+    let text = fs::read_to_string(&input).with_context(|| format!("Could not open {input}"))?;
+    let games = read_games(&text).with_context(|| format!("Could not read {input}"))?;
+    println!("read {} games", games.len());
+
+    let contents = if output.ends_with(".pgn") {
+        games
+            .iter()
+            .map(Game::to_string)
+            .collect::<Vec<_>>()
+            .join("\n")
+    } else {
+        let mut positions = String::new();
+        let mut count = 0;
+        for game in games.iter().filter(|game| game.result != "*") {
+            for fen in game.fens()? {
+                writeln!(positions, "{fen} {}", game.result)?;
+                count += 1;
+            }
+        }
+        println!("extracted {count} positions");
+        positions
+    };
+    fs::write(&output, contents).with_context(|| format!("Could not write {output}"))?;
+    println!("written to {output}");
+    Ok(())
+}
+
+pub fn read_games(text: &str) -> Result<Vec<Game>> {
+    let mut tokens = tokenize(text)?.into_iter().peekable();
+    let mut games = Vec::new();
+    while tokens.peek().is_some() {
+        let game = read_game(&mut tokens).with_context(|| format!("game {}", games.len() + 1))?;
+        games.push(game);
+    }
+    Ok(games)
+}
+
+impl Game {
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn start_position(&self) -> Result<Board> {
+        let mut board = Board::new();
+        if let Some(fen) = self.tag("FEN") {
+            board.parse_fen(fen)?;
+        }
+        Ok(board)
+    }
+
+    pub fn mainline(&self) -> impl Iterator<Item = Action> + '_ {
+        self.line.moves.iter().map(|pgn_move| pgn_move.action)
+    }
+
+    // every position of the main line, including the starting position and the final one
+    pub fn fens(&self) -> Result<Vec<String>> {
+        let mut board = self.start_position()?;
+        let mut fens = vec![board.to_fen()];
+        for action in self.mainline() {
+            board.make_move(action);
+            fens.push(board.to_fen());
+        }
+        Ok(fens)
+    }
+}
+
+fn read_game(tokens: &mut Tokens) -> Result<Game> {
+    let mut game = Game::default();
+    while let Some(Token::Tag(..)) = tokens.peek() {
+        if let Some(Token::Tag(name, value)) = tokens.next() {
+            game.tags.push((name, value));
+        }
+    }
+
+    let mut board = game.start_position()?;
+    game.line = read_line(&mut board, tokens, false)?;
+    game.result = match tokens.next() {
+        Some(Token::Result(result)) => result,
+        // a game cut off without a result is still worth keeping
+        _ => String::from("*"),
+    };
+    if game.tag("Result").is_none() {
+        game.tags
+            .push((String::from("Result"), game.result.clone()));
+    }
+    Ok(game)
+}
+
+// Reads moves until the end of the line: a closing bracket for variations, or a result or the next game for
+// the main line. The board is left as it was found.
+fn read_line(board: &mut Board, tokens: &mut Tokens, is_variation: bool) -> Result<Line> {
+    let mut line = Line::default();
+    loop {
+        match tokens.peek() {
+            None | Some(Token::Tag(..)) | Some(Token::Result(_)) if !is_variation => break,
+            None | Some(Token::Tag(..)) | Some(Token::Result(_)) => bail!("Unclosed variation"),
+            _ => (),
+        }
+        match tokens.next().unwrap() {
+            Token::Move(san) => {
+                let action = board
+                    .parse_san(&san)
+                    .with_context(|| format!("Illegal move {san} in {}", board.to_fen()))?;
+                board.make_move(action);
+                line.moves.push(PgnMove {
+                    action,
+                    nags: Vec::new(),
+                    eval: None,
+                    comment: None,
+                    variations: Vec::new(),
+                });
+            }
+            Token::Comment(comment) => match line.moves.last_mut() {
+                Some(pgn_move) => pgn_move.add_comment(&comment),
+                None => append(&mut line.comment, &comment),
+            },
+            Token::Nag(nag) => {
+                let pgn_move = line.moves.last_mut().context("Annotation without a move")?;
+                pgn_move.nags.push(nag);
+            }
+            Token::Open => {
+                // a variation replaces the move before it
+                let pgn_move = line.moves.last_mut().context("Variation without a move")?;
+                board.unmake_move(pgn_move.action);
+                let variation = read_line(board, tokens, true)?;
+                board.make_move(pgn_move.action);
+                pgn_move.variations.push(variation);
+            }
+            Token::Close if is_variation => break,
+            Token::Close => bail!("Unexpected ')'"),
+            Token::Tag(..) | Token::Result(_) => unreachable!(),
+        }
+    }
+    for pgn_move in line.moves.iter().rev() {
+        board.unmake_move(pgn_move.action);
+    }
+    Ok(line)
+}
+
+impl PgnMove {
+    // an evaluation is only looked for at the start of the first comment
+    fn add_comment(&mut self, comment: &str) {
+        if self.eval.is_none() && self.comment.is_none() {
+            let (first, rest) = comment.split_once(' ').unwrap_or((comment, ""));
+            if let Some(eval) = parse_eval(first) {
+                self.eval = Some(eval);
+                if !rest.trim().is_empty() {
+                    self.comment = Some(rest.trim().to_string());
+                }
+                return;
+            }
+        }
+        append(&mut self.comment, comment);
+    }
+}
+
+fn append(comment: &mut Option<String>, text: &str) {
+    let text = text.trim();
+    if text.is_empty() {
+        return;
+    }
+    match comment {
+        Some(comment) => {
+            comment.push(' ');
+            comment.push_str(text);
+        }
+        None => *comment = Some(text.to_string()),
+    }
+}
+
+// +0.35/12, -M5/20 and the like
+fn parse_eval(text: &str) -> Option<Eval> {
+    let (score, depth) = text.split_once('/')?;
+    let depth = depth.parse().ok()?;
+    let (negative, magnitude) = match score.strip_prefix('-') {
+        Some(magnitude) => (true, magnitude),
+        None => (false, score.strip_prefix('+').unwrap_or(score)),
+    };
+    let sign = if negative { -1 } else { 1 };
+    let score = match magnitude.strip_prefix('M') {
+        Some(moves) => PgnScore::Mate(sign * moves.parse::<i32>().ok()?),
+        None => {
+            PgnScore::Centipawns(sign * (magnitude.parse::<f64>().ok()? * 100.0).round() as i32)
+        }
+    };
+    Some(Eval { score, depth })
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>> {
+    // a % only escapes the line when it is in the first column
+    let text = text
+        .lines()
+        .filter(|line| !line.starts_with('%'))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(character) = chars.next() {
+        match character {
+            '[' => {
+                let name = chars
+                    .by_ref()
+                    .take_while(|&character| character != '"')
+                    .collect::<String>();
+                let mut value = String::new();
+                while let Some(character) = chars.next() {
+                    match character {
+                        '\\' => value.extend(chars.next()),
+                        '"' => break,
+                        _ => value.push(character),
+                    }
+                }
+                chars.by_ref().find(|&character| character == ']');
+                tokens.push(Token::Tag(name.trim().to_string(), value));
+            }
+            '{' => {
+                let comment = chars
+                    .by_ref()
+                    .take_while(|&character| character != '}')
+                    .collect::<String>();
+                tokens.push(Token::Comment(
+                    comment.split_whitespace().collect::<Vec<_>>().join(" "),
+                ));
+            }
+            // rest of line comments
+            ';' => {
+                let comment = chars
+                    .by_ref()
+                    .take_while(|&character| character != '\n')
+                    .collect::<String>();
+                tokens.push(Token::Comment(comment.trim().to_string()));
+            }
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '*' => tokens.push(Token::Result(String::from("*"))),
+            '$' => {
+                let mut nag = String::new();
+                while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                    nag.push(digit);
+                }
+                tokens.push(Token::Nag(nag.parse().context("Invalid NAG")?));
+            }
+            character if character.is_whitespace() || character == '.' => (),
+            _ => {
+                let mut symbol = String::from(character);
+                while let Some(character) = chars.next_if(|&character| {
+                    character.is_ascii_alphanumeric() || "+#=:-/_".contains(character)
+                }) {
+                    symbol.push(character);
+                }
+                // move numbers are skipped, and the next move read after the dots
+                if symbol.chars().all(|character| character.is_ascii_digit()) {
+                    continue;
+                }
+                if RESULTS.contains(&symbol.as_str()) {
+                    tokens.push(Token::Result(symbol));
+                    continue;
+                }
+
+                let mut suffix = String::new();
+                while let Some(character) = chars.next_if(|&character| "!?".contains(character)) {
+                    suffix.push(character);
+                }
+                tokens.push(Token::Move(symbol));
+                let nag = match suffix.as_str() {
+                    "" => None,
+                    "!" => Some(1),
+                    "?" => Some(2),
+                    "!!" => Some(3),
+                    "??" => Some(4),
+                    "!?" => Some(5),
+                    "?!" => Some(6),
+                    _ => bail!("Invalid annotation {suffix}"),
+                };
+                tokens.extend(nag.map(Token::Nag));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+impl fmt::Display for Game {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in self.tags.iter() {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            writeln!(f, "[{name} \"{value}\"]")?;
+        }
+        writeln!(f)?;
+
+        let mut words = Vec::new();
+        let mut board = self.start_position().map_err(|_| fmt::Error)?;
+        write_line(&mut board, &self.line, &mut words);
+        words.push(self.result.clone());
+
+        // movetext is wrapped between words
+        let mut line = String::new();
+        for word in words {
+            if !line.is_empty() && line.len() + word.len() + 1 > LINE_LENGTH {
+                writeln!(f, "{line}")?;
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&word);
+        }
+        writeln!(f, "{line}")
+    }
+}
+
+impl fmt::Display for Eval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.score {
+            PgnScore::Centipawns(score) => write!(f, "{:+.2}", score as f64 / 100.0)?,
+            PgnScore::Mate(moves) if moves < 0 => write!(f, "-M{}", -moves)?,
+            PgnScore::Mate(moves) => write!(f, "+M{moves}")?,
+        }
+        write!(f, "/{}", self.depth)
+    }
+}
+
+// The board is left as it was found. Black's moves get a number of their own at the start of a line, and
+// after anything that interrupts the moves.
+fn write_line(board: &mut Board, line: &Line, words: &mut Vec<String>) {
+    if let Some(comment) = &line.comment {
+        words.push(format!("{{{comment}}}"));
+    }
+    let mut needs_number = true;
+    for pgn_move in line.moves.iter() {
+        // numbers are kept on the same line as their move
+        let number = board.fullmove_number();
+        let san = board.to_san(pgn_move.action);
+        words.push(if board.active_color() == Color::W {
+            format!("{number}. {san}")
+        } else if needs_number {
+            format!("{number}... {san}")
+        } else {
+            san
+        });
+        words.extend(pgn_move.nags.iter().map(|nag| format!("${nag}")));
+
+        let comment = match (pgn_move.eval, &pgn_move.comment) {
+            (Some(eval), Some(comment)) => Some(format!("{eval} {comment}")),
+            (Some(eval), None) => Some(eval.to_string()),
+            (None, comment) => comment.clone(),
+        };
+        needs_number = comment.is_some() || !pgn_move.variations.is_empty();
+        if let Some(comment) = comment {
+            words.push(format!("{{{comment}}}"));
+        }
+
+        for variation in pgn_move.variations.iter() {
+            let start = words.len();
+            write_line(board, variation, words);
+            if words.len() > start {
+                words[start].insert(0, '(');
+                words.last_mut().unwrap().push(')');
+            }
+        }
+        board.make_move(pgn_move.action);
+    }
+    for pgn_move in line.moves.iter().rev() {
+        board.unmake_move(pgn_move.action);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn only_game(text: &str) -> Game {
+        let mut games = read_games(text).unwrap();
+        assert_eq!(games.len(), 1);
+        games.pop().unwrap()
+    }
+
+    // the main line in standard algebraic notation
+    fn sans(board: &mut Board, line: &Line) -> Vec<String> {
+        let mut sans = Vec::new();
+        for pgn_move in line.moves.iter() {
+            sans.push(board.to_san(pgn_move.action));
+            board.make_move(pgn_move.action);
+        }
+        for pgn_move in line.moves.iter().rev() {
+            board.unmake_move(pgn_move.action);
+        }
+        sans
+    }
+
+    #[test]
+    fn tokens() {
+        let text = "[Event \"The \\\"Big\\\" One\"]\n[Site \"?\"]\n\n1. e4 {a\n  comment} 1... e5 $14 2.Nf3 ( 2. f4!? ) ; to the end of the line\n1-0";
+        assert_eq!(
+            tokenize(text).unwrap(),
+            [
+                Token::Tag("Event".to_string(), "The \"Big\" One".to_string()),
+                Token::Tag("Site".to_string(), "?".to_string()),
+                Token::Move("e4".to_string()),
+                Token::Comment("a comment".to_string()),
+                Token::Move("e5".to_string()),
+                Token::Nag(14),
+                Token::Move("Nf3".to_string()),
+                Token::Open,
+                Token::Move("f4".to_string()),
+                Token::Nag(5),
+                Token::Close,
+                Token::Comment("to the end of the line".to_string()),
+                Token::Result("1-0".to_string()),
+            ]
+        );
+        assert_eq!(
+            tokenize("1. O-O-O 1/2-1/2 0-1 *").unwrap(),
+            [
+                Token::Move("O-O-O".to_string()),
+                Token::Result("1/2-1/2".to_string()),
+                Token::Result("0-1".to_string()),
+                Token::Result("*".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn annotations() {
+        for (suffix, nag) in [
+            ("!", 1),
+            ("?", 2),
+            ("!!", 3),
+            ("??", 4),
+            ("!?", 5),
+            ("?!", 6),
+        ] {
+            assert_eq!(
+                tokenize(&format!("e4{suffix}")).unwrap(),
+                [Token::Move("e4".to_string()), Token::Nag(nag)],
+                "{suffix}"
+            );
+        }
+        assert!(tokenize("e4!!?").is_err());
+        assert!(tokenize("e4 $x").is_err());
+
+        let game = only_game("1. e4!? e5 $2 $18 *");
+        assert_eq!(game.line.moves[0].nags, [5]);
+        assert_eq!(game.line.moves[1].nags, [2, 18]);
+    }
+
+    #[test]
+    fn escaped_lines() {
+        let game = only_game("%1. d4 d5\n1. e4 {50% of the time} e5 *");
+        let mut board = game.start_position().unwrap();
+        assert_eq!(sans(&mut board, &game.line), ["e4", "e5"]);
+        assert_eq!(
+            game.line.moves[0].comment.as_deref(),
+            Some("50% of the time")
+        );
+
+        // anywhere else a % isn't an escape
+        assert!(read_games("1. e4 % e5 *").is_err());
+    }
+
+    #[test]
+    fn variations() {
+        let game = only_game("1. e4 e5 (1... c5 2. Nf3 (2. c3) 2... d6) (1... e6) 2. Nf3 *");
+        let mut board = game.start_position().unwrap();
+        assert_eq!(sans(&mut board, &game.line), ["e4", "e5", "Nf3"]);
+
+        let e5 = &game.line.moves[1];
+        assert_eq!(e5.variations.len(), 2);
+        board.make_move(game.line.moves[0].action);
+        assert_eq!(sans(&mut board, &e5.variations[0]), ["c5", "Nf3", "d6"]);
+        assert_eq!(sans(&mut board, &e5.variations[1]), ["e6"]);
+
+        // the nested variation replaces 2. Nf3
+        let sicilian = &e5.variations[0];
+        board.make_move(sicilian.moves[0].action);
+        assert_eq!(sans(&mut board, &sicilian.moves[1].variations[0]), ["c3"]);
+
+        assert!(read_games("1. e4 (1. d4 *").is_err());
+        assert!(read_games("1. e4 ) *").is_err());
+        assert!(read_games("( 1. e4 ) *").is_err());
+        assert!(read_games("1. e4 e4 *").is_err());
+    }
+
+    #[test]
+    fn evals() {
+        let eval = |score, depth| Some(Eval { score, depth });
+        assert_eq!(parse_eval("+0.35/12"), eval(PgnScore::Centipawns(35), 12));
+        assert_eq!(parse_eval("-1.5/7"), eval(PgnScore::Centipawns(-150), 7));
+        assert_eq!(parse_eval("0.00/1"), eval(PgnScore::Centipawns(0), 1));
+        assert_eq!(parse_eval("+M3/20"), eval(PgnScore::Mate(3), 20));
+        assert_eq!(parse_eval("-M5/18"), eval(PgnScore::Mate(-5), 18));
+        assert_eq!(parse_eval("book"), None);
+        assert_eq!(parse_eval("+0.35"), None);
+        assert_eq!(parse_eval("1/2"), eval(PgnScore::Centipawns(100), 2));
+
+        let game = only_game(
+            "1. e4 {+0.35/12 0.52s} e5 {-0.20/10} {+0.10/9 second comment} 2. Nf3 {book} *",
+        );
+        let moves = &game.line.moves;
+        assert_eq!(moves[0].eval, eval(PgnScore::Centipawns(35), 12));
+        assert_eq!(moves[0].comment.as_deref(), Some("0.52s"));
+        // only the first comment can hold an eval
+        assert_eq!(moves[1].eval, eval(PgnScore::Centipawns(-20), 10));
+        assert_eq!(moves[1].comment.as_deref(), Some("+0.10/9 second comment"));
+        assert_eq!(moves[2].eval, None);
+        assert_eq!(moves[2].comment.as_deref(), Some("book"));
+    }
+
+    #[test]
+    fn round_trip() {
+        let text = "[Event \"Test\"]\n[FEN \"r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 20\"]\n\n{start} 20... O-O \
+            {+0.10/5 fast} 21. O-O-O $1 (21. Ra7 Rf7 {-M2/3} (21... Rab8)) 21... Rf2?? 22. Rd8+ Rf8 23. Rxf8+ 1-0\n";
+        let game = only_game(text);
+        assert_eq!(game.tag("Result"), Some("1-0"));
+        let written = game.to_string();
+        assert_eq!(
+            written,
+            "[Event \"Test\"]\n[FEN \"r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 20\"]\n[Result \"1-0\"]\n\n{start} \
+            20... O-O {+0.10/5 fast} 21. O-O-O $1 (21. Ra7 Rf7 {-M2/3} (21... Rab8))\n\
+            21... Rf2 $4 22. Rd8+ Rf8 23. Rxf8+ 1-0\n"
+        );
+
+        let reread = only_game(&written);
+        assert_eq!(reread.to_string(), written);
+        assert_eq!(reread.fens().unwrap(), game.fens().unwrap());
+    }
+}
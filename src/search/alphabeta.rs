@@ -1,3 +1,5 @@
+use std::sync::atomic::Ordering;
+
 use crossbeam::channel::TryRecvError;
 
 use crate::{
@@ -14,10 +16,12 @@ use crate::{
 
 use super::{
     hashtable::{ALPHA, BETA, EXACT},
-    searchcontrol::{Searcher, CHECKMATE},
+    searchcontrol::{root_index, SearchCommand, Searcher, CHECKMATE},
 };
 
 pub const MAX_DEPTH: usize = 64;
+// a tablebase win, less the distance from the root
+const TB_WIN: i16 = CHECKMATE - 2 * MAX_DEPTH as i16;
 impl Searcher {
     pub fn alphabeta<const IS_ROOT: bool>(
         &mut self,
@@ -33,32 +37,23 @@ impl Searcher {
             if IS_ROOT {
                 let mut movelist = MoveList::new();
                 self.board.genmoves::<{ GenType::ALL }>(&mut movelist);
-                if movelist.len() == 0 {
+                if movelist.is_empty() {
                     panic!("No legal moves!")
-                } else {
-                    pvline.push(*movelist[0])
+                }
+                // in multipv mode, moves that have already been reported can't be reported again
+                let unreported = movelist
+                    .iter()
+                    .find(|&action| !self.is_root_excluded(**action))
+                    .map(|action| **action);
+                if let Some(action) = unreported {
+                    pvline.push(action)
                 }
             }
             return 0;
         }
 
         self.nodecount += 1;
-        if self.nodecount >= self.timer.max_nodes {
-            self.timer.stopped = true;
-        }
-
-        if check_for_stop(self.nodecount) {
-            let has_msg = match self.stop.try_recv() {
-                Ok(value) => value,
-                Err(TryRecvError::Empty) => false,
-                Err(TryRecvError::Disconnected) => panic!("Channel disconnected!"),
-            };
-            self.timer.stopped |= has_msg;
-        }
-
-        if self.timer.is_timed && check_for_stop(self.nodecount) {
-            self.timer.stopped |= self.timer.check_time();
-        }
+        self.poll_stop();
 
         if self.timer.stopped {
             return 0;
@@ -73,10 +68,7 @@ impl Searcher {
 
         let is_pv = beta - alpha != 1;
         let zobrist_key = self.board.zobrist();
-        let tt_entry = self.tt.probe(zobrist_key);
-        let tt_data = unsafe { *tt_entry };
-
-        if tt_data.key_equals(zobrist_key) {
+        if let Some(tt_data) = self.tt.probe(zobrist_key) {
             best_move = tt_data.bestmove;
             let score = tt_data.score;
             let shoulduse = match tt_data.get_nodetype() {
@@ -105,7 +97,7 @@ impl Searcher {
 
         if depth <= 0 {
             self.nodecount -= 1;
-            let qvalue = self.quiesce(0, alpha, beta);
+            let qvalue = self.quiesce(alpha, beta);
             if self.timer.stopped {
                 return 0;
             } else {
@@ -113,7 +105,21 @@ impl Searcher {
             }
         }
 
-        let eval = self.board.evaluate();
+        // Right after a capture or pawn move, a position small enough has its result in the tablebases.
+        // Wins are scored below any mate, so the search still prefers to find one.
+        if !IS_ROOT && self.board.halfmove_clock() == 0 && self.tablebases.can_probe(&self.board) {
+            if let Some(wdl) = self.tablebases.probe_wdl(&mut self.board) {
+                self.tb_hits.fetch_add(1, Ordering::Relaxed);
+                return match wdl {
+                    2 => TB_WIN - ply as i16,
+                    -2 => -TB_WIN + ply as i16,
+                    // the 50 move rule turns these into draws, but keep them apart from real draws
+                    wdl => wdl as i16,
+                };
+            }
+        }
+
+        let eval = self.board.evaluate(&mut self.pawn_table);
 
         // Null move pruning
         if !in_check && !is_pv && eval >= beta && !IS_ROOT && !self.board.is_kp() {
@@ -156,6 +162,10 @@ impl Searcher {
 
         let mut generator = StagedGenerator::new(best_move, ply);
         while let Some((action, stage)) = generator.next_move(&self.ord, &mut self.board) {
+            if IS_ROOT && self.is_root_excluded(action) {
+                continue;
+            }
+
             let lmp_margin = (depth + 1) * 4;
             if num_moves != 0
                 && depth < 5
@@ -183,6 +193,7 @@ impl Searcher {
             let mut new_pv_line = PVLine::new();
             new_pv_line.push(action);
             num_moves += 1;
+            let nodes_before = self.nodecount;
 
             let mut score: i16;
 
@@ -223,6 +234,10 @@ impl Searcher {
 
             self.board.unmake_move(action);
 
+            if IS_ROOT {
+                self.root_nodes[root_index(action)] += self.nodecount - nodes_before;
+            }
+
             if self.timer.stopped {
                 return 0;
             }
@@ -237,14 +252,8 @@ impl Searcher {
                 }
 
                 if score >= beta {
-                    unsafe {
-                        tt_entry.as_mut().unwrap().store(
-                            zobrist_key,
-                            action,
-                            score,
-                            depth as u8,
-                            BETA,
-                        );
+                    if !IS_ROOT || self.searches_whole_root() {
+                        self.tt.store(zobrist_key, action, score, depth as u8, BETA);
                     }
 
                     if stage == Stage::Quiets || stage == Stage::Killers {
@@ -276,16 +285,10 @@ impl Searcher {
             }
         }
 
-        if !stored_move {
-            unsafe {
-                tt_entry.as_mut().unwrap().store(
-                    zobrist_key,
-                    best_move,
-                    best_score,
-                    depth as u8,
-                    nodetype,
-                );
-            }
+        // a root search with excluded moves doesn't represent the actual position, so keep it out of the table
+        if !stored_move && (!IS_ROOT || self.searches_whole_root()) {
+            self.tt
+                .store(zobrist_key, best_move, best_score, depth as u8, nodetype);
         }
 
         for value in best_pvline.iter() {
@@ -295,30 +298,15 @@ impl Searcher {
         best_score
     }
 
-    fn quiesce(&mut self, ply: u16, mut alpha: i16, beta: i16) -> i16 {
+    fn quiesce(&mut self, mut alpha: i16, beta: i16) -> i16 {
         self.nodecount += 1;
         self.qnodecount += 1;
-        if self.nodecount >= self.timer.max_nodes {
-            self.timer.stopped = true;
-        }
-
-        if check_for_stop(self.nodecount) {
-            let has_msg = match self.stop.try_recv() {
-                Ok(value) => value,
-                Err(TryRecvError::Empty) => false,
-                Err(TryRecvError::Disconnected) => panic!("Channel disconnected!"),
-            };
-            self.timer.stopped |= has_msg;
-        }
-
-        if self.timer.is_timed && check_for_stop(self.nodecount) {
-            self.timer.stopped |= self.timer.check_time()
-        }
+        self.poll_stop();
 
         if self.timer.stopped {
             return 0;
         }
-        let bestscore = self.board.evaluate();
+        let bestscore = self.board.evaluate(&mut self.pawn_table);
 
         if bestscore >= beta {
             return bestscore;
@@ -336,7 +324,7 @@ impl Searcher {
             }
             self.board.make_move(action);
 
-            let score = -self.quiesce(ply + 1, -beta, -alpha);
+            let score = -self.quiesce(-beta, -alpha);
             self.board.unmake_move(action);
             if score > alpha {
                 alpha = score;
@@ -348,11 +336,58 @@ impl Searcher {
         }
         alpha
     }
+
+    #[inline]
+    fn is_root_excluded(&self, action: Action) -> bool {
+        self.root_excluded.contains(&action) || self.tb_excluded.contains(&action)
+    }
+
+    #[inline]
+    fn searches_whole_root(&self) -> bool {
+        self.root_excluded.is_empty() && self.tb_excluded.is_empty()
+    }
+
+    // Only the main thread listens for uci stops and keeps track of time and nodes. Helpers stop once the
+    // main thread raises the shared stop flag.
+    fn poll_stop(&mut self) {
+        if self.nodecount >= self.timer.max_nodes {
+            self.timer.stopped = true;
+        }
+
+        if !check_for_stop(self.nodecount) {
+            return;
+        }
+
+        self.shared_nodes.store(self.nodecount, Ordering::Relaxed);
+        if self.is_main() {
+            match self.stop.try_recv() {
+                // a stop ends pondering too, so the search mustn't wait for another command afterwards
+                Ok(SearchCommand::Stop) => {
+                    self.timer.stopped = true;
+                    self.timer.pondering = false;
+                }
+                Ok(SearchCommand::PonderHit) => self.timer.ponderhit(),
+                Err(TryRecvError::Empty) => (),
+                Err(TryRecvError::Disconnected) => panic!("Channel disconnected!"),
+            }
+
+            if self.timer.is_timed {
+                self.timer.stopped |= self.timer.check_time();
+            }
+
+            self.timer.stopped |= self.total_nodes() >= self.timer.max_nodes;
+
+            if self.timer.stopped {
+                self.stop_flag.store(true, Ordering::Relaxed);
+            }
+        }
+        self.timer.stopped |= self.stop_flag.load(Ordering::Relaxed);
+    }
 }
 
 const fn check_for_stop(nodecount: u64) -> bool {
     const CHECK_TIME: u64 = 4096;
-    nodecount % CHECK_TIME == 0
+    nodecount.is_multiple_of(CHECK_TIME)
 }
 
 const fn is_mate(score: i16) -> bool {
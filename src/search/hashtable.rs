@@ -1,42 +1,148 @@
-
-// 64 bits: zob key  8 bytes
+// 64 bits: zob key ^ data  8 bytes
 // 16 bits: best move 2 bytes
 // 16 bits: score 2 bytes
 // 2 bits: node type 1 byte
 // 6 bits: depth
-// total: 13 bytes
+// 8 bits: generation 1 byte
+// total: 16 bytes, as two atomic words. 4 entries share a 64 byte cache line.
 
-use std::mem;
+use std::{
+    mem,
+    sync::atomic::{AtomicU64, AtomicU8, Ordering},
+};
 
 use crate::movegen::{action::Action, zobrist::Zobrist};
 
 pub struct TranspositionTable {
-    table: Vec<Entry>,
+    table: Vec<Bucket>,
+    generation: AtomicU8,
 }
 
-const CONVERSION: usize = (1 << 20) / mem::size_of::<Entry>();
+const BUCKET_SIZE: usize = 4;
+const CONVERSION: usize = (1 << 20) / mem::size_of::<Bucket>();
 
 impl TranspositionTable {
     pub fn new(size: usize) -> Self {
         // size is in MB
-        let entries = size * CONVERSION;
-        if entries == 0 {
+        let buckets = size * CONVERSION;
+        if buckets == 0 {
             return Self {
-                table: vec![Entry::new(); 1],
+                table: vec![Bucket::default()],
+                generation: AtomicU8::new(0),
             };
         }
         Self {
-            table: vec![Entry::new(); entries],
+            table: (0..buckets).map(|_| Bucket::default()).collect(),
+            generation: AtomicU8::new(0),
         }
     }
-    pub fn probe(&mut self, key: Zobrist) -> *mut Entry {
-        let idx = key as usize % self.table.len();
-        &mut self.table[idx]
+
+    // Returns a copy of the entry stored for this position, if there is one.
+    pub fn probe(&self, key: Zobrist) -> Option<Entry> {
+        self.bucket(key)
+            .entries
+            .iter()
+            .map(|slot| slot.load())
+            .find(|entry| entry.key_equals(key))
     }
-    pub fn clear(&mut self) {
-        for i in self.table.iter_mut() {
-            (*i).store(0, Action::default(), 0, 0, 0);
+
+    pub fn store(&self, key: Zobrist, bestmove: Action, eval: i16, depth: u8, nodetype: NodeType) {
+        let generation = self.generation();
+        let bucket = self.bucket(key);
+
+        let mut new_entry = Entry::new();
+        new_entry.set(key, bestmove, eval, depth, nodetype, generation);
+
+        // If the position is already in the bucket, overwrite it, but don't forget its best move.
+        // Otherwise, evict whichever entry is the least valuable, preferring stale and shallow ones.
+        let mut victim = &bucket.entries[0];
+        let mut victim_value = i16::MAX;
+        for slot in bucket.entries.iter() {
+            let entry = slot.load();
+            if entry.key_equals(key) {
+                if bestmove == Action::default() {
+                    new_entry.bestmove = entry.bestmove;
+                }
+                slot.save(new_entry);
+                return;
+            }
+
+            let value = entry.replace_value(generation);
+            if value < victim_value {
+                victim = slot;
+                victim_value = value;
+            }
         }
+        victim.save(new_entry);
+    }
+
+    // Called at the start of every search, so entries from previous searches can be told apart.
+    pub fn new_search(&self) {
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Approximates how full the table is in per-mille, by sampling the first thousand or so entries
+    // and counting how many were written during the current search.
+    pub fn hashfull(&self) -> u16 {
+        const SAMPLE_BUCKETS: usize = 1000 / BUCKET_SIZE;
+        let generation = self.generation();
+        let sampled = self.table.iter().take(SAMPLE_BUCKETS);
+        let num_sampled = sampled.len() * BUCKET_SIZE;
+        let used = sampled
+            .flat_map(|bucket| bucket.entries.iter())
+            .map(|slot| slot.load())
+            .filter(|entry| entry.key != 0 && entry.generation == generation)
+            .count();
+        (used * 1000 / num_sampled) as u16
+    }
+
+    pub fn clear(&self) {
+        for bucket in self.table.iter() {
+            for slot in bucket.entries.iter() {
+                slot.save(Entry::new());
+            }
+        }
+        self.generation.store(0, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn generation(&self) -> u8 {
+        self.generation.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    fn bucket(&self, key: Zobrist) -> &Bucket {
+        &self.table[key as usize % self.table.len()]
+    }
+}
+
+#[derive(Default)]
+#[repr(align(64))]
+struct Bucket {
+    entries: [AtomicEntry; BUCKET_SIZE],
+}
+
+// Lazy smp threads read and write the table concurrently without locking. The key is stored xored with the
+// data, so if two threads write the same slot at once, the torn entry fails the key check on the next probe
+// and is simply treated as a miss.
+#[derive(Default)]
+#[repr(align(16))]
+struct AtomicEntry {
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+impl AtomicEntry {
+    fn load(&self) -> Entry {
+        let data = self.data.load(Ordering::Relaxed);
+        let key = self.key.load(Ordering::Relaxed) ^ data;
+        Entry::unpack(key, data)
+    }
+
+    fn save(&self, entry: Entry) {
+        let data = entry.pack();
+        self.key.store(entry.key ^ data, Ordering::Relaxed);
+        self.data.store(data, Ordering::Relaxed);
     }
 }
 
@@ -46,12 +152,12 @@ pub const EXACT: NodeType = 0b11;
 pub const ALPHA: NodeType = 0b01;
 
 #[derive(Clone, Copy)]
-#[repr(align(16))]
 pub struct Entry {
     pub key: u64,
     pub bestmove: Action,
     pub score: i16,
     pub otherdata: u8,
+    pub generation: u8,
 }
 
 impl Default for Entry {
@@ -67,15 +173,25 @@ impl Entry {
             bestmove: Action::default(),
             score: 0,
             otherdata: EXACT,
+            generation: 0,
         }
     }
 
-    pub fn store(&mut self, key: u64, bestmove: Action, eval: i16, depth: u8, nodetype: NodeType) {
+    pub fn set(
+        &mut self,
+        key: u64,
+        bestmove: Action,
+        eval: i16,
+        depth: u8,
+        nodetype: NodeType,
+        generation: u8,
+    ) {
         let data = (depth << 2) | nodetype;
         self.key = key;
         self.bestmove = bestmove;
         self.score = eval;
         self.otherdata = data;
+        self.generation = generation;
     }
 
     pub fn get_depth(&self) -> u8 {
@@ -89,4 +205,27 @@ impl Entry {
     pub fn key_equals(&self, key: Zobrist) -> bool {
         self.key == key
     }
-}
\ No newline at end of file
+
+    // how much an entry is worth keeping. Every search it has survived costs it 8 plies of depth.
+    fn replace_value(&self, generation: u8) -> i16 {
+        let age = generation.wrapping_sub(self.generation) as i16;
+        self.get_depth() as i16 - 8 * age
+    }
+
+    fn pack(&self) -> u64 {
+        (self.bestmove.0 as u64)
+            | (self.score as u16 as u64) << 16
+            | (self.otherdata as u64) << 32
+            | (self.generation as u64) << 40
+    }
+
+    fn unpack(key: u64, data: u64) -> Entry {
+        Entry {
+            key,
+            bestmove: Action(data as u16),
+            score: (data >> 16) as u16 as i16,
+            otherdata: (data >> 32) as u8,
+            generation: (data >> 40) as u8,
+        }
+    }
+}
@@ -1,143 +1,670 @@
-use std::{time::Instant, fmt::Write};
+use std::{
+    cmp, mem,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Instant,
+};
 
-use crossbeam::channel::Receiver;
+use crossbeam::channel::{self, Receiver};
 
-use crate::movegen::{action::Action, board::Board, movelist::List};
+use crate::{
+    eval::{nnue::Network, params::EvalParams, pawns::PawnTable},
+    movegen::{
+        action::Action,
+        board::Board,
+        genmoves::GenType,
+        movelist::{List, MoveList},
+    },
+    syzygy::Tablebases,
+};
 
 use super::{
     alphabeta::{mated_in, MAX_DEPTH},
     hashtable::TranspositionTable,
     moveorder::OrderData,
-    timer::Timer,
+    timer::{SearchLimits, Timer, MAX_MIN_THINK_TIME, MAX_MOVE_OVERHEAD},
 };
 
 pub struct Searcher {
     pub(super) nodecount: u64,
     pub(super) qnodecount: u64,
-    pub timer: Timer,
-    pub(super) tt: TranspositionTable,
-    pub(super) stop: Receiver<bool>,
+    pub(super) timer: Timer,
+    pub(super) tt: Arc<TranspositionTable>,
+    pub(super) stop: Receiver<SearchCommand>,
     pub(super) board: Board,
     pub(super) ord: OrderData,
+    pub(super) pawn_table: PawnTable,
+    pub(super) multipv: usize,
+    pub(super) root_excluded: Vec<Action>,
+    pub(super) root_nodes: Vec<u64>, // nodes spent on each root move, indexed by from and to squares
+    pub(super) tablebases: Arc<Tablebases>,
+    pub(super) tb_excluded: Vec<Action>, // root moves the tablebases show to be worse than the best ones
+    pub(super) tb_hits: Arc<AtomicU64>,  // shared by every thread
+    pub(super) iterations: Vec<Iteration>,
+    reporter: Option<Reporter>, // only the main thread reports
+
+    // lazy smp. Thread 0 is the main thread, which owns the helpers and decides when to stop.
+    pub(super) thread_id: usize,
+    pub(super) stop_flag: Arc<AtomicBool>,
+    pub(super) shared_nodes: Arc<AtomicU64>,
+    helpers: Vec<Searcher>,
+    helper_nodes: Vec<Arc<AtomicU64>>,
+}
+
+// what the main thread had found at the end of each iteration of the last search
+#[derive(Clone, Copy, Debug)]
+pub struct Iteration {
+    pub depth: i16,
+    pub score: i16,
+    pub mate: Option<i16>, // moves until mate, negative if getting mated
+    pub best_move: Action,
+    pub nodes: u64,
+    pub time: u64, // ms
+}
+
+// The main thread's progress on one line, sent to the reporter whenever an iteration finishes and whenever the
+// score falls outside of the aspiration window.
+#[derive(Clone, Debug)]
+pub struct SearchInfo {
+    pub depth: i16,
+    pub line: usize,  // counting from 1
+    pub lines: usize, // from set_multipv
+    pub score: i16,
+    pub mate: Option<i16>,
+    pub bound: Bound,
+    pub nodes: u64,
+    pub tb_hits: u64,
+    pub hashfull: u16, // permill
+    pub time: u64,     // ms
+    pub pv: Vec<Action>,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bound {
+    Exact,
+    Lower, // failed high, the score is at least this
+    Upper, // failed low, the score is at most this
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchResult {
+    pub best_move: Option<Action>, // None if there are no legal moves
+    pub ponder_move: Option<Action>,
+    pub iterations: Vec<Iteration>,
+}
+
+pub type Reporter = Box<dyn FnMut(&SearchInfo) + Send>;
+
+// messages from the uci thread to a running search
+#[derive(Clone, Copy, Debug)]
+pub enum SearchCommand {
+    Stop,
+    PonderHit,
+}
 
 pub(super) const CHECKMATE: i16 = 10_000;
+pub const MAX_THREADS: usize = 256;
+pub const MAX_MULTIPV: usize = 256;
 
 pub(super) type PVLine = List<Action, 64>;
+
+// a searcher that nothing can stop early, it only runs out of limits
+impl Default for Searcher {
+    fn default() -> Self {
+        Self::new(channel::never())
+    }
+}
+
 impl Searcher {
-    pub fn search(&mut self) {
+    // Searches the board until the limits run out or a stop command comes in. A ponder search doesn't return
+    // until the gui has said either stop or ponderhit.
+    pub fn search(&mut self, limits: &SearchLimits) -> SearchResult {
+        self.timer.set_limits(limits, self.board.active_color());
+        self.timer.start_time = Instant::now();
+        self.iterations.clear();
+        self.stop_flag.store(false, Ordering::Relaxed);
+        self.tt.new_search();
+        self.tb_hits.store(0, Ordering::Relaxed);
+        self.tb_excluded = self.tablebase_root_filter();
+        let handles = self.spawn_helpers();
+
+        let (mut best_move, mut ponder_move) = self.iterative_deepening();
+
+        // the main thread is done, so bring the helpers down with it
+        self.stop_flag.store(true, Ordering::Relaxed);
+        for handle in handles {
+            self.helpers
+                .push(handle.join().expect("Error: Helper Thread Panicked"));
+        }
+
+        // while pondering, bestmove can't be sent until the gui says either stop or ponderhit
+        if self.timer.pondering {
+            self.stop.recv().expect("Error: UCI Thread Disconnected");
+        }
+
+        // the search can be cut off before it finishes the first iteration when time is very short
+        if best_move == Action::default() {
+            let mut movelist = MoveList::new();
+            self.board.genmoves::<{ GenType::ALL }>(&mut movelist);
+            if movelist.is_empty() {
+                self.timer.refresh();
+                return SearchResult {
+                    best_move: None,
+                    ponder_move: None,
+                    iterations: mem::take(&mut self.iterations),
+                };
+            }
+            best_move = *movelist[0];
+        }
+
+        if ponder_move.is_none() {
+            ponder_move = self.ponder_from_tt(best_move);
+        }
+
+        self.timer.refresh();
+        SearchResult {
+            best_move: Some(best_move),
+            ponder_move,
+            iterations: mem::take(&mut self.iterations),
+        }
+    }
+
+    // The pv is often cut short by a transposition table hit, so look for the reply in the table instead.
+    fn ponder_from_tt(&mut self, best_move: Action) -> Option<Action> {
+        if best_move == Action::default() {
+            return None;
+        }
+
+        self.board.make_move(best_move);
+        let reply = self
+            .tt
+            .probe(self.board.zobrist())
+            .map(|entry| entry.bestmove)
+            .filter(|&reply| reply != Action::default() && self.board.is_pseudolegal(reply))
+            .filter(|&reply| {
+                self.board.make_move(reply);
+                let is_legal = !self.board.in_check(!self.board.active_color());
+                self.board.unmake_move(reply);
+                is_legal
+            });
+        self.board.unmake_move(best_move);
+        reply
+    }
+
+    // Leaves only the root moves with the best tablebase result to search. The search still picks between them,
+    // as dtz alone doesn't make progress towards mate.
+    fn tablebase_root_filter(&mut self) -> Vec<Action> {
+        if !self.tablebases.can_probe(&self.board) {
+            return Vec::new();
+        }
+        let Some(ranked) = self.tablebases.rank_root_moves(&mut self.board) else {
+            return Vec::new();
+        };
+        self.tb_hits
+            .fetch_add(ranked.len() as u64, Ordering::Relaxed);
+        let best_rank = ranked.iter().map(|&(_, rank)| rank).max().unwrap_or(0);
+        ranked
+            .into_iter()
+            .filter(|&(_, rank)| rank < best_rank)
+            .map(|(action, _)| action)
+            .collect()
+    }
+
+    fn spawn_helpers(&mut self) -> Vec<JoinHandle<Searcher>> {
+        mem::take(&mut self.helpers)
+            .into_iter()
+            .map(|mut helper| {
+                helper.board = self.board.clone();
+                helper.tt = self.tt.clone();
+                helper.tablebases = self.tablebases.clone();
+                helper.tb_excluded = self.tb_excluded.clone();
+                helper.tb_hits = self.tb_hits.clone();
+                helper.shared_nodes.store(0, Ordering::Relaxed);
+                thread::spawn(move || {
+                    helper.iterative_deepening();
+                    helper.timer.refresh();
+                    helper
+                })
+            })
+            .collect()
+    }
+
+    fn iterative_deepening(&mut self) -> (Action, Option<Action>) {
         let global_time = Instant::now();
         self.refresh();
-        self.timer.start_time = Instant::now();
         let mut best_move = Action::default();
+        let mut ponder_move = None;
         let mut pv = PVLine::new();
-        let mut depth = 0;
-        let alpha = -CHECKMATE;
-        let beta = CHECKMATE;
-        loop {
+        // helpers on odd threads start one ply deeper, so that the threads don't all search in lockstep
+        let mut depth = if self.is_main() {
+            0
+        } else {
+            (self.thread_id % 2) as i16
+        };
+        // only the main thread reports multiple lines, helpers just help find the best one
+        let num_lines = if self.is_main() { self.multipv } else { 1 };
+        let mut prev_scores: Vec<Option<i16>> = vec![None; num_lines];
+        let mut found_mate = false;
+        let mut stability = 0;
+        let mut score_drop = 0;
+        'deepening: loop {
             depth += 1;
             if depth >= MAX_DEPTH as i16 {
                 break;
             }
 
-            pv.clear();
-            let score = self.alphabeta::<true>(depth , 0, alpha, beta, &mut pv);
+            // each line is searched with the root moves of the lines before it excluded
+            for line in 1..=num_lines {
+                let score =
+                    self.aspiration(depth, line, prev_scores[line - 1], &mut pv, global_time);
 
-            
-            if self.timer.stopped {
+                if self.timer.stopped {
+                    self.root_excluded.clear();
+                    break 'deepening;
+                }
+
+                if pv.is_empty() {
+                    // there are fewer legal moves than lines
+                    break;
+                }
+
+                if line == 1 {
+                    if pv[0] == best_move {
+                        stability += 1;
+                    } else {
+                        stability = 0;
+                    }
+                    score_drop = prev_scores[0].map_or(0, |prev_score| prev_score - score);
+
+                    found_mate = self.timer.mate.is_some_and(|max_moves| {
+                        mate_moves(score).is_some_and(|moves| moves > 0 && moves <= max_moves)
+                    });
+                    best_move = pv[0];
+                    ponder_move = if pv.len() > 1 && pv[1] != Action::default() {
+                        Some(pv[1])
+                    } else {
+                        None
+                    };
+                }
+                self.root_excluded.push(pv[0]);
+                prev_scores[line - 1] = Some(score);
+
+                if self.is_main() && line == 1 {
+                    self.iterations.push(Iteration {
+                        depth,
+                        score,
+                        mate: mate_moves(score),
+                        best_move,
+                        nodes: self.total_nodes(),
+                        time: global_time.elapsed().as_millis() as u64,
+                    });
+                }
+                if self.is_main() {
+                    let elapsed = global_time.elapsed().as_millis() as u64;
+                    self.report(depth, line, score, Bound::Exact, &pv, elapsed);
+                }
+            }
+            self.root_excluded.clear();
+
+            if !self.is_main() {
+                continue;
+            }
+
+            let scale = self.time_scale(best_move, stability, score_drop);
+            if depth as u8 >= self.timer.max_depth || self.timer.soft_stop(scale) || found_mate {
                 break;
             }
+        }
+        self.shared_nodes.store(self.nodecount, Ordering::Relaxed);
+        (best_move, ponder_move)
+    }
 
-            let mut scoretype = "cp";
-            let mut reported_score = score;
-            if mated_in(score) < 64 && mated_in(score) > -64 {
-                scoretype = "mate";
-                let mate_score = mated_in(score);
-                reported_score = if mate_score % 2 == 0 {
-                    mate_score / 2
-                } else {
-                    (mate_score / 2) + 1
-                };
+    // How much to scale the soft time limit by. More time is spent when the best move keeps changing, when
+    // the score is dropping, or when the best move's subtree took up only a small part of the search.
+    fn time_scale(&self, best_move: Action, stability: usize, score_drop: i16) -> f64 {
+        const STABILITY_SCALES: [f64; 5] = [1.6, 1.3, 1.1, 0.95, 0.85];
+        let stability_scale = STABILITY_SCALES[cmp::min(stability, STABILITY_SCALES.len() - 1)];
+
+        let drop_scale = 1.0 + score_drop.clamp(0, 100) as f64 / 200.0;
+
+        let best_nodes = self.root_nodes[root_index(best_move)];
+        let best_fraction = best_nodes as f64 / cmp::max(self.nodecount, 1) as f64;
+        let node_scale = (1.5 - best_fraction) * 1.35;
+
+        stability_scale * drop_scale * node_scale
+    }
+
+    // Searches the root with a window centered on the score from the previous iteration. If the score falls
+    // outside of it, the window is widened on that side and the root is searched again.
+    fn aspiration(
+        &mut self,
+        depth: i16,
+        line: usize,
+        prev_score: Option<i16>,
+        pv: &mut PVLine,
+        global_time: Instant,
+    ) -> i16 {
+        const MIN_DEPTH: i16 = 4;
+        const INITIAL_WINDOW: i16 = 25;
+
+        let mut delta = INITIAL_WINDOW;
+        let (mut alpha, mut beta) = match prev_score {
+            Some(score) if depth >= MIN_DEPTH => (
+                cmp::max(score - delta, -CHECKMATE),
+                cmp::min(score + delta, CHECKMATE),
+            ),
+            _ => (-CHECKMATE, CHECKMATE),
+        };
+
+        loop {
+            pv.clear();
+            let score = self.alphabeta::<true>(depth, 0, alpha, beta, pv);
+            if self.timer.stopped {
+                return score;
             }
 
-            let elapsed = global_time.elapsed().as_millis() as u64;
-            let nps = if elapsed == 0 {
-                0
+            let bound = if score <= alpha && alpha > -CHECKMATE {
+                // fail low. Pull beta down as well, since the previous score was too optimistic
+                beta = (alpha + beta) / 2;
+                alpha = cmp::max(score - delta, -CHECKMATE);
+                Bound::Upper
+            } else if score >= beta && beta < CHECKMATE {
+                beta = cmp::min(score + delta, CHECKMATE);
+                Bound::Lower
             } else {
-                self.nodecount * 1000 / elapsed
+                return score;
             };
 
-            println!(
-                "info depth {} score {} {} nodes {} nps {} time {} pv{}",
-                depth,
-                scoretype,
-                reported_score,
-                self.nodecount,
-                nps,
-                elapsed,
-                format_pv(&pv)
-            );
-            
-            best_move = pv[0];
-            if depth as u8 >= self.timer.max_depth
-                || elapsed > self.timer.time_alloted
-            {
-                break;
+            if self.is_main() && !pv.is_empty() {
+                let elapsed = global_time.elapsed().as_millis() as u64;
+                self.report(depth, line, score, bound, pv, elapsed);
             }
+            delta += delta / 2;
         }
+    }
 
-        println!("bestmove {}", best_move);
-        self.timer.refresh();
+    fn report(
+        &mut self,
+        depth: i16,
+        line: usize,
+        score: i16,
+        bound: Bound,
+        pv: &PVLine,
+        elapsed: u64,
+    ) {
+        if self.reporter.is_none() {
+            return;
+        }
+        let info = SearchInfo {
+            depth,
+            line,
+            lines: self.multipv,
+            score,
+            mate: mate_moves(score),
+            bound,
+            nodes: self.total_nodes(),
+            tb_hits: self.tb_hits.load(Ordering::Relaxed),
+            hashfull: self.tt.hashfull(),
+            time: elapsed,
+            pv: pv
+                .iter()
+                .copied()
+                .take_while(|&action| action != Action::default())
+                .collect(),
+        };
+        if let Some(reporter) = self.reporter.as_mut() {
+            reporter(&info);
+        }
     }
 
-    pub fn new(recv: Receiver<bool>) -> Self {
+    pub fn new(recv: Receiver<SearchCommand>) -> Self {
+        Self::with_shared(
+            0,
+            recv,
+            Arc::new(TranspositionTable::new(64)),
+            Arc::new(AtomicBool::new(false)),
+        )
+    }
+
+    fn with_shared(
+        thread_id: usize,
+        recv: Receiver<SearchCommand>,
+        tt: Arc<TranspositionTable>,
+        stop_flag: Arc<AtomicBool>,
+    ) -> Self {
         Searcher {
             nodecount: 0,
             qnodecount: 0,
             timer: Timer::default(),
-            tt: TranspositionTable::new(64),
+            tt,
             stop: recv,
             board: Board::new(),
             ord: OrderData::new(),
+            pawn_table: PawnTable::new(),
+            multipv: 1,
+            root_excluded: Vec::new(),
+            root_nodes: vec![0; 64 * 64],
+            tablebases: Arc::new(Tablebases::default()),
+            tb_excluded: Vec::new(),
+            tb_hits: Arc::new(AtomicU64::new(0)),
+            iterations: Vec::new(),
+            reporter: None,
+            thread_id,
+            stop_flag,
+            shared_nodes: Arc::new(AtomicU64::new(0)),
+            helpers: Vec::new(),
+            helper_nodes: Vec::new(),
         }
     }
 
     pub fn reset(&mut self) {
+        let params = self.board.params().clone();
+        let network = self.board.network();
+        let chess960 = self.board.is_chess960();
         self.board = Board::new();
+        self.board.set_params(params);
+        self.board.set_network(network);
+        self.board.set_chess960(chess960);
         self.tt.clear();
         self.ord.clear();
         self.nodecount = 0;
         self.qnodecount = 0;
-        self.timer = Timer::new();
+        self.timer.refresh();
+        for helper in self.helpers.iter_mut() {
+            helper.ord.clear();
+        }
+    }
+
+    pub fn set_threads(&mut self, threads: usize) {
+        let num_helpers = threads.clamp(1, MAX_THREADS) - 1;
+        self.helpers = (1..=num_helpers)
+            .map(|thread_id| {
+                // helpers never receive uci commands, they only listen to the main thread's stop flag
+                Searcher::with_shared(
+                    thread_id,
+                    channel::never(),
+                    self.tt.clone(),
+                    self.stop_flag.clone(),
+                )
+            })
+            .collect();
+        self.helper_nodes = self
+            .helpers
+            .iter()
+            .map(|helper| helper.shared_nodes.clone())
+            .collect();
+    }
+
+    pub fn set_multipv(&mut self, multipv: usize) {
+        self.multipv = multipv.clamp(1, MAX_MULTIPV);
     }
 
     pub fn hash_resize(&mut self, new_size: usize) {
-        self.tt = TranspositionTable::new(new_size);
+        self.tt = Arc::new(TranspositionTable::new(new_size));
     }
 
     pub fn clear_hash(&mut self) {
         self.tt.clear()
     }
 
+    // an empty path unloads the network, going back to the psqts. Helpers pick it up from the board.
+    pub fn set_eval_file(&mut self, path: &str) -> anyhow::Result<()> {
+        let network = match path {
+            "" | "<empty>" => None,
+            path => Some(Arc::new(Network::load(path)?)),
+        };
+        self.board.set_network(network);
+        Ok(())
+    }
+
+    // an empty path goes back to the compiled in parameters
+    pub fn set_param_file(&mut self, path: &str) -> anyhow::Result<()> {
+        let params = match path {
+            "" | "<empty>" => EvalParams::default(),
+            path => EvalParams::load(path)?,
+        };
+        self.board.set_params(Arc::new(params));
+        self.pawn_table.clear();
+        for helper in self.helpers.iter_mut() {
+            helper.pawn_table.clear();
+        }
+        Ok(())
+    }
+
+    // an empty path unloads the tablebases. Returns how many were found.
+    pub fn set_syzygy_path(&mut self, path: &str) -> anyhow::Result<usize> {
+        let tablebases = match path {
+            "" | "<empty>" => Tablebases::default(),
+            path => Tablebases::load(path)?,
+        };
+        let count = tablebases.len();
+        self.tablebases = Arc::new(tablebases);
+        Ok(count)
+    }
+
     pub fn get_board(&mut self) -> &mut Board {
         &mut self.board
     }
 
+    // called with the main thread's progress during every search, which is otherwise silent
+    pub fn set_reporter(&mut self, reporter: impl FnMut(&SearchInfo) + Send + 'static) {
+        self.reporter = Some(Box::new(reporter));
+    }
+
+    pub fn set_move_overhead(&mut self, move_overhead: u64) {
+        self.timer.move_overhead = cmp::min(move_overhead, MAX_MOVE_OVERHEAD);
+    }
+
+    pub fn set_min_think_time(&mut self, min_think_time: u64) {
+        self.timer.min_think_time = cmp::min(min_think_time, MAX_MIN_THINK_TIME);
+    }
+
+    #[inline]
+    pub(super) fn is_main(&self) -> bool {
+        self.thread_id == 0
+    }
+
+    // nodes searched by every thread, as last published by the helpers
+    pub(super) fn total_nodes(&self) -> u64 {
+        self.nodecount
+            + self
+                .helper_nodes
+                .iter()
+                .map(|nodes| nodes.load(Ordering::Relaxed))
+                .sum::<u64>()
+    }
+
     fn refresh(&mut self) {
         self.ord.age_history();
+        self.root_nodes.fill(0);
         self.nodecount = 0;
         self.qnodecount = 0;
     }
 }
 
-pub fn format_pv(pv: &PVLine) -> String {
-    let mut starting_str = String::new();
-    for action in pv.iter() {
-        if *action == Action::default() {
-            break;
+#[inline]
+pub(super) fn root_index(action: Action) -> usize {
+    action.from() as usize * 64 + action.to() as usize
+}
+
+// the number of moves until mate, negative if we are the ones getting mated
+fn mate_moves(score: i16) -> Option<i16> {
+    let mate_score = mated_in(score);
+    if mate_score < 64 && mate_score > -64 {
+        if mate_score % 2 == 0 {
+            Some(mate_score / 2)
+        } else {
+            Some((mate_score / 2) + 1)
+        }
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use crossbeam::channel::{self, RecvTimeoutError};
+
+    use super::{SearchCommand, SearchResult, Searcher};
+    use crate::search::timer::SearchLimits;
+
+    // runs the search on its own thread, so that a hang fails the test instead of blocking it forever
+    fn search_with_commands(
+        limits: SearchLimits,
+        commands: &[(u64, SearchCommand)],
+    ) -> SearchResult {
+        let (tx, rx) = channel::unbounded();
+        let (result_tx, result_rx) = channel::bounded(1);
+        thread::spawn(move || {
+            let mut searcher = Searcher::new(rx);
+            result_tx.send(searcher.search(&limits)).unwrap();
+        });
+        for &(delay, command) in commands {
+            thread::sleep(Duration::from_millis(delay));
+            tx.send(command).unwrap();
+        }
+        match result_rx.recv_timeout(Duration::from_secs(30)) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => panic!("search never returned"),
+            Err(RecvTimeoutError::Disconnected) => panic!("search thread panicked"),
         }
-        write!(&mut starting_str, " {}", action).unwrap();
     }
-    starting_str
-}
\ No newline at end of file
+
+    #[test]
+    fn stop_while_pondering() {
+        let limits = SearchLimits {
+            ponder: true,
+            ..SearchLimits::default()
+        };
+        let result = search_with_commands(limits, &[(200, SearchCommand::Stop)]);
+        assert!(result.best_move.is_some());
+    }
+
+    #[test]
+    fn stop_after_ponder_search_finished() {
+        let limits = SearchLimits {
+            ponder: true,
+            depth: Some(2),
+            ..SearchLimits::default()
+        };
+        let result = search_with_commands(limits, &[(500, SearchCommand::Stop)]);
+        assert!(result.best_move.is_some());
+    }
+
+    #[test]
+    fn ponderhit_then_time_runs_out() {
+        let limits = SearchLimits {
+            ponder: true,
+            wtime: Some(1000),
+            btime: Some(1000),
+            ..SearchLimits::default()
+        };
+        let result = search_with_commands(limits, &[(200, SearchCommand::PonderHit)]);
+        assert!(result.best_move.is_some());
+    }
+}
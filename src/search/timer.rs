@@ -1,12 +1,42 @@
 use std::{cmp, time::Instant};
 
+use crate::movegen::types::Color;
+
+pub const DEFAULT_MOVE_OVERHEAD: u64 = 10;
+pub const MAX_MOVE_OVERHEAD: u64 = 5000;
+pub const DEFAULT_MIN_THINK_TIME: u64 = 20;
+pub const MAX_MIN_THINK_TIME: u64 = 5000;
+
+// What a search is allowed to spend, as given by go. Anything left as None is unlimited, and only the clock of
+// the side to move is used.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SearchLimits {
+    pub wtime: Option<u64>, // ms
+    pub btime: Option<u64>, // ms
+    pub winc: u64,          // ms
+    pub binc: u64,          // ms
+    pub movestogo: Option<u64>,
+    pub movetime: Option<u64>, // ms
+    pub depth: Option<u8>,
+    pub nodes: Option<u64>,
+    pub mate: Option<i16>, // moves
+    pub ponder: bool,      // the clock doesn't start until ponderhit
+}
+
 pub struct Timer {
-    pub time_alloted: u64, //ms
+    pub soft_limit: u64, //ms, decides whether to start another iteration
+    pub hard_limit: u64, //ms, aborts the search
     pub max_nodes: u64,
     pub max_depth: u8,
+    pub mate: Option<i16>, // moves
     pub start_time: Instant,
     pub is_timed: bool,
+    pub pondering: bool,
     pub stopped: bool,
+
+    // uci options, these persist between searches
+    pub move_overhead: u64,  //ms, reserved for lag between us and the gui
+    pub min_think_time: u64, //ms
 }
 impl Default for Timer {
     fn default() -> Self {
@@ -16,32 +46,131 @@ impl Default for Timer {
 impl Timer {
     pub fn new() -> Timer {
         Timer {
-            time_alloted: u64::MAX,
+            soft_limit: u64::MAX,
+            hard_limit: u64::MAX,
             max_nodes: u64::MAX,
             max_depth: u8::MAX,
+            mate: None,
             start_time: Instant::now(),
             is_timed: false,
+            pondering: false,
             stopped: false,
+            move_overhead: DEFAULT_MOVE_OVERHEAD,
+            min_think_time: DEFAULT_MIN_THINK_TIME,
         }
     }
-    
     pub fn check_time(&self) -> bool {
-        self.start_time.elapsed().as_millis() as u64 >= self.time_alloted
+        // the time spent pondering is on the opponent's clock
+        !self.pondering && self.elapsed() >= self.hard_limit
+    }
+
+    // Whether there is enough time left to start another iteration. The soft limit is scaled by how
+    // confident the search is in its best move, but can never go past the hard limit.
+    pub fn soft_stop(&self, scale: f64) -> bool {
+        if self.pondering {
+            return false;
+        }
+
+        // a fixed movetime has nothing to scale
+        let soft_limit = if self.soft_limit < self.hard_limit {
+            cmp::min((self.soft_limit as f64 * scale) as u64, self.hard_limit)
+        } else {
+            self.soft_limit
+        };
+        self.elapsed() >= soft_limit
+    }
+
+    fn elapsed(&self) -> u64 {
+        self.start_time.elapsed().as_millis() as u64
+    }
+
+    // the opponent played the expected move, so the clock starts now
+    pub fn ponderhit(&mut self) {
+        self.pondering = false;
+        self.start_time = Instant::now();
+    }
+
+    // sets the soft and hard limits for a side with this much time left
+    pub fn allocate_time(&mut self, timeleft: u64, inc: u64, movestogo: Option<u64>) {
+        // whatever is lost to lag comes off of our clock, so it can't be used for thinking
+        let available = timeleft.saturating_sub(self.move_overhead);
+
+        // with a repeating time control, the remaining time only has to last until the next time control.
+        // otherwise, assume that the game will last about 30 more moves. a movestogo of 0 makes no sense,
+        // so it is treated as if it wasn't given.
+        let moves_left = match movestogo.filter(|&movestogo| movestogo > 0) {
+            Some(movestogo) => cmp::min(movestogo, 30) + 1,
+            None => 30,
+        };
+
+        // avoid allocating more than 1/8 of the time to avoid time pressure, unless the time control is
+        // about to refresh anyway
+        let cannot_exceed = cmp::max(available / cmp::min(moves_left, 8), 1);
+
+        let soft_limit = cmp::min((available / moves_left) + inc / 4, cannot_exceed);
+        let hard_limit = cmp::min(soft_limit * 4, cannot_exceed);
+
+        // think for at least the minimum time, as long as that doesn't flag
+        let soft_limit = cmp::max(soft_limit, self.min_think_time);
+        let hard_limit = cmp::max(hard_limit, self.min_think_time);
+        self.soft_limit = cmp::min(soft_limit, available);
+        self.hard_limit = cmp::min(hard_limit, available);
     }
 
-    pub fn allocate_time(timeleft: u64, inc: u64) -> u64 {
-        let cannot_exceed = cmp::max(timeleft / 8, 1); // avoid allocating more than 1/8 of the time to avoid time pressure
-        
-        let timeleft = (timeleft / 30) + inc / 4;
-        cmp::min(timeleft, cannot_exceed)
+    pub fn set_limits(&mut self, limits: &SearchLimits, color: Color) {
+        let (timeleft, inc) = match color {
+            Color::W => (limits.wtime, limits.winc),
+            Color::B => (limits.btime, limits.binc),
+        };
+        if let Some(timeleft) = timeleft {
+            self.allocate_time(timeleft, inc, limits.movestogo);
+        }
+        if let Some(movetime) = limits.movetime {
+            self.limit_movetime(movetime);
+        }
+        self.is_timed = self.hard_limit != u64::MAX;
+        self.pondering = limits.ponder;
+        self.max_nodes = limits.nodes.unwrap_or(u64::MAX);
+        self.max_depth = limits.depth.unwrap_or(u8::MAX);
+        self.mate = limits.mate.filter(|&moves| moves > 0);
+    }
+
+    pub fn limit_movetime(&mut self, movetime: u64) {
+        let movetime = movetime.saturating_sub(self.move_overhead);
+        self.soft_limit = cmp::min(self.soft_limit, movetime);
+        self.hard_limit = cmp::min(self.hard_limit, movetime);
     }
 
     pub fn refresh(&mut self) {
-        self.time_alloted = u64::MAX;
+        self.soft_limit = u64::MAX;
+        self.hard_limit = u64::MAX;
         self.max_nodes = u64::MAX;
         self.max_depth = u8::MAX;
+        self.mate = None;
         self.start_time = Instant::now();
         self.is_timed = false;
+        self.pondering = false;
         self.stopped = false;
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Timer;
+
+    #[test]
+    fn movestogo() {
+        let mut timer = Timer::new();
+        timer.allocate_time(60_000, 0, Some(0));
+        let (soft_limit, hard_limit) = (timer.soft_limit, timer.hard_limit);
+        timer.allocate_time(60_000, 0, None);
+        assert_eq!(
+            (soft_limit, hard_limit),
+            (timer.soft_limit, timer.hard_limit)
+        );
+
+        // the last move before the time control can use more, but never the whole clock
+        timer.allocate_time(60_000, 0, Some(1));
+        assert!(timer.hard_limit < 60_000 - timer.move_overhead);
+    }
+}
@@ -0,0 +1,390 @@
+// Syzygy endgame tablebases. WDL tables give the result of a position with the 50 move rule in mind, and DTZ
+// tables the distance to the next capture or pawn move on the way to that result. The search probes WDL
+// tables, and the root moves are narrowed down with DTZ tables before searching.
+//
+// WDL values go from -2 to 2: loss, blessed loss (a loss saved by the 50 move rule), draw, cursed win (a win
+// spoiled by the 50 move rule) and win.
+
+mod table;
+
+use std::{collections::HashMap, env, fs, path::Path, sync::Arc};
+
+use anyhow::{Context, Result};
+
+use crate::movegen::{
+    action::{Action, MoveType},
+    board::Board,
+    genmoves::GenType,
+    movelist::MoveList,
+    types::Piece,
+};
+
+use self::table::{Kind, Probe, Table};
+
+const MAX_DTZ: i32 = 1 << 18;
+
+struct Entry {
+    wdl: Table,
+    dtz: Option<Table>,
+}
+
+// Every table found is indexed by its material signature, with white as either side. A table's file is only
+// read the first time it is probed.
+#[derive(Default)]
+pub struct Tablebases {
+    entries: HashMap<u64, Arc<Entry>>,
+    max_pieces: usize,
+}
+
+impl Tablebases {
+    // a list of directories, separated like PATH
+    pub fn load(paths: &str) -> Result<Tablebases> {
+        let mut tablebases = Tablebases::default();
+        for directory in env::split_paths(paths) {
+            let files = fs::read_dir(&directory)
+                .with_context(|| format!("Could not read {}", directory.display()))?;
+            for file in files {
+                let path = file?.path();
+                if path
+                    .extension()
+                    .is_some_and(|extension| extension == "rtbw")
+                {
+                    tablebases.add(&path);
+                }
+            }
+        }
+        Ok(tablebases)
+    }
+
+    fn add(&mut self, path: &Path) {
+        let Some(name) = path.file_stem().and_then(|name| name.to_str()) else {
+            return;
+        };
+        let Some(wdl) = Table::new(Kind::Wdl, path.to_path_buf(), name) else {
+            return;
+        };
+        let dtz_path = path.with_extension("rtbz");
+        let dtz = if dtz_path.exists() {
+            Table::new(Kind::Dtz, dtz_path, name)
+        } else {
+            None
+        };
+
+        self.max_pieces = self.max_pieces.max(wdl.piece_count);
+        let keys = wdl.keys();
+        let entry = Arc::new(Entry { wdl, dtz });
+        for key in keys {
+            self.entries.insert(key, entry.clone());
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        let mut tables = self.entries.values().map(Arc::as_ptr).collect::<Vec<_>>();
+        tables.sort_unstable();
+        tables.dedup();
+        tables.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // whether the position is small enough, and has no castling rights, which the tables don't know about
+    pub fn can_probe(&self, board: &Board) -> bool {
+        board.occupancy().count_ones() as usize <= self.max_pieces && !board.has_castling_rights()
+    }
+
+    // the wdl value from the side to move's point of view, or None if a table is missing
+    pub fn probe_wdl(&self, board: &mut Board) -> Option<i32> {
+        self.search(board, false).map(|(wdl, _)| wdl)
+    }
+
+    // Plies to the next capture or pawn move, with the sign of the wdl value. Cursed wins and blessed losses
+    // have 100 added. None if a table is missing.
+    pub fn probe_dtz(&self, board: &mut Board) -> Option<i32> {
+        let (wdl, zeroing_best) = self.search(board, true)?;
+        if wdl == 0 {
+            return Some(0);
+        }
+        // the table doesn't store anything meaningful when the best move is a capture or pawn move
+        if zeroing_best {
+            return Some(dtz_before_zeroing(wdl));
+        }
+
+        let dtz_table = &self.entry(board)?.dtz.as_ref()?;
+        if let Probe::Value(dtz) = dtz_table.probe(board, wdl)? {
+            let cursed = if wdl.abs() == 1 { 100 } else { 0 };
+            return Some((dtz + cursed) * wdl.signum());
+        }
+
+        // The table only has the other side to move, so look one ply ahead for the move with the lowest dtz
+        // that keeps the result.
+        let mut movelist = MoveList::new();
+        board.genmoves::<{ GenType::ALL }>(&mut movelist);
+        let mut min_dtz = i32::MAX;
+        for action in movelist.iter().map(|action| **action) {
+            let zeroing = is_capture(board, action) || board.get_piece(action.from()) == Piece::P;
+            board.make_move(action);
+            let dtz = if zeroing {
+                self.search(board, false)
+                    .map(|(wdl, _)| -dtz_before_zeroing(wdl))
+            } else {
+                self.probe_dtz(board).map(|dtz| -dtz)
+            };
+            let mates = dtz == Some(1) && is_checkmate(board);
+            board.unmake_move(action);
+
+            let mut dtz = dtz?;
+            if mates {
+                min_dtz = 1;
+            }
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz < min_dtz && dtz.signum() == wdl.signum() {
+                min_dtz = dtz;
+            }
+        }
+        // no legal moves means we are mated
+        Some(if min_dtz == i32::MAX { -1 } else { min_dtz })
+    }
+
+    // Ranks every root move by its dtz: wins that can be converted within the 50 move rule first, then slower
+    // wins, draws, and losses. None if a table is missing.
+    pub fn rank_root_moves(&self, board: &mut Board) -> Option<Vec<(Action, i32)>> {
+        let halfmove_clock = board.halfmove_clock() as i32;
+        let mut movelist = MoveList::new();
+        board.genmoves::<{ GenType::ALL }>(&mut movelist);
+
+        let mut ranked = Vec::new();
+        for action in movelist.iter().map(|action| **action) {
+            board.make_move(action);
+            let dtz = if board.halfmove_clock() == 0 {
+                self.probe_wdl(board).map(|wdl| dtz_before_zeroing(-wdl))
+            } else if board.is_draw() || board.is_repetition(1) {
+                Some(0)
+            } else {
+                self.probe_dtz(board).map(|dtz| -dtz - dtz.signum())
+            };
+            let mates = is_checkmate(board);
+            board.unmake_move(action);
+
+            let mut dtz = dtz?;
+            if mates && dtz == 2 {
+                dtz = 1;
+            }
+            let rank = if dtz > 0 {
+                if dtz + halfmove_clock <= 99 {
+                    MAX_DTZ
+                } else {
+                    MAX_DTZ - (dtz + halfmove_clock)
+                }
+            } else if dtz < 0 {
+                if -dtz * 2 + halfmove_clock < 100 {
+                    -MAX_DTZ
+                } else {
+                    -MAX_DTZ + (-dtz + halfmove_clock)
+                }
+            } else {
+                0
+            };
+            ranked.push((action, rank));
+        }
+        Some(ranked)
+    }
+
+    fn entry(&self, board: &Board) -> Option<&Arc<Entry>> {
+        self.entries.get(&board.material_signature())
+    }
+
+    // Tables don't store a meaningful value when the best move is a winning capture, so captures are always
+    // searched as well. With check_zeroing pawn moves are searched too, for dtz. Returns the wdl value and
+    // whether the best move is a capture or pawn move.
+    fn search(&self, board: &mut Board, check_zeroing: bool) -> Option<(i32, bool)> {
+        let mut movelist = MoveList::new();
+        board.genmoves::<{ GenType::ALL }>(&mut movelist);
+
+        let mut best = -2;
+        let mut searched = 0;
+        for action in movelist.iter().map(|action| **action) {
+            let capture = is_capture(board, action);
+            if !capture && (!check_zeroing || board.get_piece(action.from()) != Piece::P) {
+                continue;
+            }
+            searched += 1;
+
+            board.make_move(action);
+            let value = self.search(board, false).map(|(wdl, _)| -wdl);
+            board.unmake_move(action);
+
+            let value = value?;
+            if value > best {
+                best = value;
+                if value >= 2 {
+                    return Some((value, true));
+                }
+            }
+        }
+
+        // with every move searched, the table isn't needed
+        let no_more_moves = searched != 0 && searched == movelist.len();
+        let value = if no_more_moves {
+            best
+        } else if board.occupancy().count_ones() == 2 {
+            0
+        } else {
+            match self.entry(board)?.wdl.probe(board, 0)? {
+                Probe::Value(value) => value,
+                Probe::WrongSide => return None,
+            }
+        };
+
+        if best >= value {
+            Some((best, best > 0 || no_more_moves))
+        } else {
+            Some((value, false))
+        }
+    }
+}
+
+fn is_capture(board: &Board, action: Action) -> bool {
+    match action.move_type() {
+        MoveType::Passant => true,
+        MoveType::Castle => false,
+        _ => board.get_piece(action.to()) != Piece::None,
+    }
+}
+
+fn is_checkmate(board: &mut Board) -> bool {
+    let mut movelist = MoveList::new();
+    board.genmoves::<{ GenType::ALL }>(&mut movelist);
+    movelist.is_empty() && board.in_check(board.active_color())
+}
+
+// the dtz of a position whose best move is a capture or pawn move
+fn dtz_before_zeroing(wdl: i32) -> i32 {
+    match wdl {
+        2 => 1,
+        1 => 101,
+        -1 => -101,
+        -2 => -1,
+        _ => 0,
+    }
+}
+
+// These need the real tables, which aren't in the repository, so they only run when asked for:
+// SYZYGY_PATH=<tables> cargo test --release -- --ignored syzygy
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::{is_checkmate, Tablebases};
+    use crate::movegen::board::Board;
+
+    type Expected = (&'static str, i32);
+
+    const THREE_PIECES_WDL: [Expected; 14] = [
+        ("4k3/8/8/8/8/8/8/3QK3 w - - 0 1", 2),
+        ("4k3/8/8/8/8/8/8/3QK3 b - - 0 1", -2),
+        ("4k3/8/8/8/8/8/8/R3K3 b - - 0 1", -2),
+        ("4k3/8/8/8/8/8/8/2B1K3 w - - 0 1", 0),
+        ("4k3/8/8/8/8/8/8/2N1K3 b - - 0 1", 0),
+        // the same positions the kpk bitbase is checked against
+        ("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1", 2),
+        ("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1", -2),
+        ("8/8/4k3/8/4K3/4P3/8/8 b - - 0 1", -2),
+        ("8/8/4k3/8/4K3/4P3/8/8 w - - 0 1", 0),
+        ("k7/8/K7/P7/8/8/8/8 w - - 0 1", 0),
+        ("8/8/4p3/4k3/8/4K3/8/8 w - - 0 1", -2),
+        // Qb7 mates at once, and with black to move the king still has b8
+        ("k7/7Q/1K6/8/8/8/8/8 w - - 0 1", 2),
+        ("k7/7Q/1K6/8/8/8/8/8 b - - 0 1", -2),
+        ("8/4P3/8/8/8/8/k7/4K3 w - - 0 1", 2),
+    ];
+
+    const THREE_PIECES_DTZ: [Expected; 3] = [
+        ("k7/7Q/1K6/8/8/8/8/8 w - - 0 1", 1),
+        // a winning pawn move is one ply from zeroing by definition
+        ("8/4P3/8/8/8/8/k7/4K3 w - - 0 1", 1),
+        ("4k3/8/8/8/8/8/8/2B1K3 w - - 0 1", 0),
+    ];
+
+    const FOUR_PIECES_WDL: [Expected; 5] = [
+        // the knight can't stop Qb7 mate
+        ("k7/7Q/1K6/8/8/8/8/n7 w - - 0 1", 2),
+        ("k7/8/8/8/3n4/8/8/3QK3 w - - 0 1", 2),
+        ("8/8/3k4/3n4/8/3N4/3K4/8 w - - 0 1", 0),
+        ("4k3/8/8/8/8/8/P7/R3K3 w - - 0 1", 2),
+        ("r3k3/p7/8/8/8/8/8/4K3 w - - 0 1", -2),
+    ];
+
+    const FOUR_PIECES_DTZ: [Expected; 2] = [
+        ("k7/7Q/1K6/8/8/8/8/n7 w - - 0 1", 1),
+        // taking the loose knight wins, so the capture is the best move
+        ("k7/8/8/8/3n4/8/8/3QK3 w - - 0 1", 1),
+    ];
+
+    fn tablebases() -> Tablebases {
+        let path = env::var("SYZYGY_PATH")
+            .expect("SYZYGY_PATH should hold the syzygy tables with up to four pieces");
+        Tablebases::load(&path).unwrap()
+    }
+
+    fn board(fen: &str) -> Board {
+        let mut board = Board::new();
+        board.parse_fen(fen).unwrap();
+        board
+    }
+
+    fn check(wdls: &[Expected], dtzs: &[Expected]) {
+        let tablebases = tablebases();
+        for &(fen, expected) in wdls {
+            let wdl = tablebases.probe_wdl(&mut board(fen));
+            assert_eq!(wdl, Some(expected), "{fen}");
+        }
+        for &(fen, expected) in dtzs {
+            let dtz = tablebases.probe_dtz(&mut board(fen));
+            assert_eq!(dtz, Some(expected), "{fen}");
+        }
+    }
+
+    // the expected values only mean something for positions that can come up in a game
+    #[test]
+    fn legal_positions() {
+        let all = [
+            &THREE_PIECES_WDL[..],
+            &THREE_PIECES_DTZ,
+            &FOUR_PIECES_WDL,
+            &FOUR_PIECES_DTZ,
+        ];
+        for &(fen, _) in all.concat().iter() {
+            let board = board(fen);
+            assert!(!board.in_check(!board.active_color()), "{fen}");
+        }
+        for fen in [
+            "k7/7Q/1K6/8/8/8/8/8 w - - 0 1",
+            "k7/7Q/1K6/8/8/8/8/n7 w - - 0 1",
+        ] {
+            let mut board = board(fen);
+            let mate = board.parse_move("h7b7").unwrap();
+            board.make_move(mate);
+            assert!(is_checkmate(&mut board), "{fen}");
+        }
+        // not stalemate either
+        assert!(board("k7/7Q/1K6/8/8/8/8/8 b - - 0 1")
+            .parse_move("a8b8")
+            .is_some());
+    }
+
+    #[test]
+    #[ignore = "needs the syzygy tables in SYZYGY_PATH"]
+    fn three_pieces() {
+        check(&THREE_PIECES_WDL, &THREE_PIECES_DTZ);
+    }
+
+    #[test]
+    #[ignore = "needs the syzygy tables in SYZYGY_PATH"]
+    fn four_pieces() {
+        check(&FOUR_PIECES_WDL, &FOUR_PIECES_DTZ);
+    }
+}
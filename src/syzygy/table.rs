@@ -0,0 +1,918 @@
+// Reading a single syzygy table: the file layout, the huffman decompression of a stored value and the
+// encoding of a position into an index into the table. Follows the layout the generator writes.
+
+use std::{fs, path::PathBuf, sync::OnceLock};
+
+use static_init::dynamic;
+
+use crate::movegen::{
+    atks,
+    bitboard::{self, Bitboard},
+    board::Board,
+    types::{square, Color, Piece, Square},
+};
+
+const MAX_PIECES: usize = 7;
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xe8, 0x23, 0x5d];
+const DTZ_MAGIC: [u8; 4] = [0xd7, 0x66, 0x0c, 0xa5];
+
+// flags of each subtable
+const STM: u8 = 1;
+const MAPPED: u8 = 2;
+const WIN_PLIES: u8 = 4;
+const LOSS_PLIES: u8 = 8;
+const WIDE: u8 = 16;
+const SINGLE_VALUE: u8 = 128;
+
+// flags in the first byte of the file
+const SPLIT: u8 = 1;
+const HAS_PAWNS: u8 = 2;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(super) enum Kind {
+    Wdl,
+    Dtz,
+}
+
+pub(super) enum Probe {
+    Value(i32),
+    // dtz tables only store one side to move
+    WrongSide,
+}
+
+pub(super) struct Table {
+    kind: Kind,
+    path: PathBuf,
+    key: u64,
+    key2: u64,
+    pub(super) piece_count: usize,
+    has_pawns: bool,
+    has_unique_pieces: bool,
+    pawn_count: [usize; 2], // the leading color's pawns and the other color's
+    // read in the first time the table is probed. None if the file turned out to be broken.
+    layout: OnceLock<Option<Layout>>,
+}
+
+struct Layout {
+    bytes: Vec<u8>,
+    subtables: Vec<PairsData>, // [side to move * 4 + leading pawn file]
+}
+
+// One subtable, compressed with recursive pairing and then huffman coding.
+#[derive(Clone, Default)]
+struct PairsData {
+    flags: u8,
+    min_sym_len: u8, // also the value of a single valued table
+    block_size: usize,
+    span: usize, // a sparse index entry about every span values
+    lowest_sym: usize,
+    btree: usize,
+    block_length: usize,
+    block_length_size: usize,
+    sparse_index: usize,
+    sparse_index_size: usize,
+    data: usize,
+    num_blocks: usize,
+    base64: Vec<u64>,
+    symlen: Vec<u8>,
+    pieces: [u8; MAX_PIECES],
+    group_idx: [u64; MAX_PIECES + 1],
+    group_len: [usize; MAX_PIECES + 1],
+    map_idx: [usize; 4], // dtz only, where the values for wins, losses, cursed wins and blessed losses start
+}
+
+impl Table {
+    // the name is like KRPvKR, white's pieces first
+    pub(super) fn new(kind: Kind, path: PathBuf, name: &str) -> Option<Table> {
+        let (white, black) = name.split_once('v')?;
+        let valid = |pieces: &str| {
+            pieces.starts_with('K') && pieces[1..].chars().all(|piece| "QRBNP".contains(piece))
+        };
+        if !valid(white) || !valid(black) || white.len() + black.len() > MAX_PIECES {
+            return None;
+        }
+
+        let count = |pieces: &str, piece: char| pieces.matches(piece).count();
+        let has_unique_pieces = [white, black]
+            .iter()
+            .any(|pieces| "QRBNP".chars().any(|piece| count(pieces, piece) == 1));
+        // the leading color is the one with fewer pawns, since that compresses better
+        let (white_pawns, black_pawns) = (count(white, 'P'), count(black, 'P'));
+        let white_leads = black_pawns == 0 || (white_pawns > 0 && black_pawns >= white_pawns);
+        let pawn_count = if white_leads {
+            [white_pawns, black_pawns]
+        } else {
+            [black_pawns, white_pawns]
+        };
+
+        Some(Table {
+            kind,
+            path,
+            key: Board::signature_of(white, black),
+            key2: Board::signature_of(black, white),
+            piece_count: white.len() + black.len(),
+            has_pawns: white_pawns + black_pawns > 0,
+            has_unique_pieces,
+            pawn_count,
+            layout: OnceLock::new(),
+        })
+    }
+
+    pub(super) fn keys(&self) -> [u64; 2] {
+        [self.key, self.key2]
+    }
+
+    // The value stored for the position, without looking at captures. None if the file can't be read.
+    pub(super) fn probe(&self, board: &Board, wdl: i32) -> Option<Probe> {
+        let layout = self.layout.get_or_init(|| self.read()).as_ref()?;
+        let bytes = &layout.bytes;
+
+        // Tables are stored with white as the stronger side, and symmetric ones only with white to move, so
+        // anything else has its colors swapped and squares flipped.
+        let us = board.active_color();
+        let symmetric_black_to_move = self.key == self.key2 && us == Color::B;
+        let black_stronger = board.material_signature() != self.key;
+        let flip = symmetric_black_to_move || black_stronger;
+        let flip_color = if flip { 8 } else { 0 };
+        let flip_squares = if flip { 56 } else { 0 };
+        let stm = flip as usize ^ us as usize;
+
+        let mut squares = [0; MAX_PIECES];
+        let mut pieces = [0; MAX_PIECES];
+        let mut size = 0;
+        let mut lead_pawns: Bitboard = 0;
+        let mut lead_pawn_count = 0;
+        let mut file = 0;
+
+        // Pawn tables are split by the file of the leading pawn, the one closest to the edge and then
+        // furthest back.
+        if self.has_pawns {
+            let lead_piece = layout.subtables[0].pieces[0] ^ flip_color;
+            let lead_color = if lead_piece & 8 != 0 {
+                Color::B
+            } else {
+                Color::W
+            };
+            lead_pawns = board.piece_bb(Piece::P, lead_color);
+            let mut pawns = lead_pawns;
+            while pawns > 0 {
+                squares[size] = bitboard::pop_lsb(&mut pawns) as usize ^ flip_squares;
+                size += 1;
+            }
+            lead_pawn_count = size;
+            let lead = (0..size).fold(0, |lead, i| {
+                if ENCODING.map_pawns[squares[i]] > ENCODING.map_pawns[squares[lead]] {
+                    i
+                } else {
+                    lead
+                }
+            });
+            squares.swap(0, lead);
+            file = edge_distance(squares[0] % 8);
+        }
+
+        if self.kind == Kind::Dtz {
+            let flags = layout.subtables[file].flags;
+            // symmetric pawnless tables work for either side to move
+            let symmetric = self.key == self.key2 && !self.has_pawns;
+            if (flags & STM) as usize != stm && !symmetric {
+                return Some(Probe::WrongSide);
+            }
+        }
+
+        let mut rest = board.occupancy() ^ lead_pawns;
+        while rest > 0 {
+            let square = bitboard::pop_lsb(&mut rest);
+            squares[size] = square as usize ^ flip_squares;
+            pieces[size] = tb_piece(board, square) ^ flip_color;
+            size += 1;
+        }
+
+        let d = layout.subtable(self, stm, file);
+
+        // put the pieces in the order the table was encoded with
+        for i in lead_pawn_count..size - 1 {
+            if let Some(j) = (i..size).find(|&j| pieces[j] == d.pieces[i]) {
+                pieces.swap(i, j);
+                squares.swap(i, j);
+            }
+        }
+
+        // the leading piece always ends up on the a to d files
+        if squares[0] % 8 > 3 {
+            for square in squares[..size].iter_mut() {
+                *square ^= 7;
+            }
+        }
+
+        let mut idx = if self.has_pawns {
+            let mut idx = ENCODING.lead_pawn_idx[lead_pawn_count][squares[0]];
+            squares[1..lead_pawn_count].sort_by_key(|&square| ENCODING.map_pawns[square]);
+            for (i, &square) in squares.iter().enumerate().take(lead_pawn_count).skip(1) {
+                idx += ENCODING.binomial[i][ENCODING.map_pawns[square]];
+            }
+            idx
+        } else {
+            self.encode_pieces(&mut squares[..size], d.group_len[0])
+        };
+
+        // then every other group, each counted among the squares the groups before it left free
+        idx *= d.group_idx[0];
+        let mut start = d.group_len[0];
+        let mut remaining_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        let mut next = 1;
+        while d.group_len[next] != 0 {
+            let len = d.group_len[next];
+            squares[start..start + len].sort_unstable();
+            let mut n = 0;
+            for i in 0..len {
+                let square = squares[start + i];
+                let adjust = squares[..start]
+                    .iter()
+                    .filter(|&&other| square > other)
+                    .count();
+                let pawn_adjust = if remaining_pawns { 8 } else { 0 };
+                n += ENCODING.binomial[i + 1][square - adjust - pawn_adjust];
+            }
+            remaining_pawns = false;
+            idx += n * d.group_idx[next];
+            start += len;
+            next += 1;
+        }
+
+        // a broken table can't take the engine down with it
+        if idx >= d.group_idx[next] {
+            return None;
+        }
+        let value = decompress(bytes, d, idx)?;
+        Some(Probe::Value(match self.kind {
+            Kind::Wdl => value - 2,
+            Kind::Dtz => map_dtz(bytes, d, value, wdl),
+        }))
+    }
+
+    // Pawnless tables use the board's symmetry: the leading piece is moved into the a1-d1-d4 triangle,
+    // and below the a1-h8 diagonal where possible.
+    fn encode_pieces(&self, squares: &mut [usize], lead_group_len: usize) -> u64 {
+        if squares[0] / 8 > 3 {
+            for square in squares.iter_mut() {
+                *square ^= 56;
+            }
+        }
+        for i in 0..lead_group_len {
+            let offset = off_diagonal(squares[i]);
+            if offset == 0 {
+                continue;
+            }
+            if offset > 0 {
+                for square in squares[i..].iter_mut() {
+                    *square = ((*square >> 3) | (*square << 3)) & 63;
+                }
+            }
+            break;
+        }
+
+        if !self.has_unique_pieces {
+            return ENCODING.map_kk[ENCODING.map_a1d1d4[squares[0]]][squares[1]];
+        }
+
+        // the first three pieces are encoded together
+        let (s0, s1, s2) = (squares[0], squares[1], squares[2]);
+        let adjust1 = (s1 > s0) as usize;
+        let adjust2 = (s2 > s0) as usize + (s2 > s1) as usize;
+        let idx = if off_diagonal(s0) != 0 {
+            (ENCODING.map_a1d1d4[s0] * 63 + (s1 - adjust1)) * 62 + s2 - adjust2
+        } else if off_diagonal(s1) != 0 {
+            (6 * 63 + (s0 / 8) * 28 + ENCODING.map_b1h1h7[s1]) * 62 + s2 - adjust2
+        } else if off_diagonal(s2) != 0 {
+            6 * 63 * 62
+                + 4 * 28 * 62
+                + (s0 / 8) * 7 * 28
+                + (s1 / 8 - adjust1) * 28
+                + ENCODING.map_b1h1h7[s2]
+        } else {
+            6 * 63 * 62
+                + 4 * 28 * 62
+                + 4 * 7 * 28
+                + (s0 / 8) * 7 * 6
+                + (s1 / 8 - adjust1) * 6
+                + (s2 / 8 - adjust2)
+        };
+        idx as u64
+    }
+
+    fn read(&self) -> Option<Layout> {
+        let bytes = fs::read(&self.path).ok()?;
+        let magic = match self.kind {
+            Kind::Wdl => WDL_MAGIC,
+            Kind::Dtz => DTZ_MAGIC,
+        };
+        if bytes.get(..4)? != magic {
+            return None;
+        }
+        let subtables = self.parse(&bytes)?;
+        Some(Layout { bytes, subtables })
+    }
+
+    fn parse(&self, bytes: &[u8]) -> Option<Vec<PairsData>> {
+        let flags = *bytes.get(4)?;
+        if (flags & HAS_PAWNS != 0) != self.has_pawns
+            || (self.kind == Kind::Wdl && (flags & SPLIT != 0) != (self.key != self.key2))
+        {
+            return None;
+        }
+
+        let sides = if self.kind == Kind::Wdl && self.key != self.key2 {
+            2
+        } else {
+            1
+        };
+        let files = if self.has_pawns { 4 } else { 1 };
+        let both_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        let mut subtables = vec![PairsData::default(); 8];
+        let mut pos = 5;
+
+        for file in 0..files {
+            let order_byte = |offset: usize| *bytes.get(pos + offset).unwrap_or(&0);
+            let second = if both_pawns { order_byte(1) } else { 0xff };
+            let orders = [
+                [order_byte(0) & 0xf, second & 0xf],
+                [order_byte(0) >> 4, second >> 4],
+            ];
+            pos += 1 + both_pawns as usize;
+
+            for k in 0..self.piece_count {
+                let byte = *bytes.get(pos)?;
+                for side in 0..sides {
+                    subtables[side * 4 + file].pieces[k] =
+                        if side == 1 { byte >> 4 } else { byte & 0xf };
+                }
+                pos += 1;
+            }
+            for (side, &order) in orders.iter().enumerate().take(sides) {
+                self.set_groups(&mut subtables[side * 4 + file], order, file);
+            }
+        }
+        pos += pos & 1;
+
+        for file in 0..files {
+            for side in 0..sides {
+                pos = set_sizes(&mut subtables[side * 4 + file], bytes, pos)?;
+            }
+        }
+
+        if self.kind == Kind::Dtz {
+            for d in subtables.iter_mut().take(files) {
+                if d.flags & MAPPED == 0 {
+                    continue;
+                }
+                if d.flags & WIDE != 0 {
+                    pos += pos & 1;
+                    for map_idx in d.map_idx.iter_mut() {
+                        *map_idx = pos + 2;
+                        pos += 2 * read_u16(bytes, pos)? as usize + 2;
+                    }
+                } else {
+                    for map_idx in d.map_idx.iter_mut() {
+                        *map_idx = pos + 1;
+                        pos += *bytes.get(pos)? as usize + 1;
+                    }
+                }
+            }
+            pos += pos & 1;
+        }
+
+        for file in 0..files {
+            for side in 0..sides {
+                let d = &mut subtables[side * 4 + file];
+                d.sparse_index = pos;
+                pos += d.sparse_index_size * 6;
+            }
+        }
+        for file in 0..files {
+            for side in 0..sides {
+                let d = &mut subtables[side * 4 + file];
+                d.block_length = pos;
+                pos += d.block_length_size * 2;
+            }
+        }
+        // single valued subtables have no blocks, so the file may end before the alignment
+        let mut end = pos;
+        for file in 0..files {
+            for side in 0..sides {
+                let d = &mut subtables[side * 4 + file];
+                pos = (pos + 63) & !63;
+                d.data = pos;
+                pos += d.num_blocks * d.block_size;
+                if d.num_blocks > 0 {
+                    end = pos;
+                }
+            }
+        }
+        (end <= bytes.len()).then_some(subtables)
+    }
+
+    // Splits the pieces up into groups of the same piece, with the leading group first, and works out how
+    // much each group's index is multiplied by. The order groups are multiplied in is stored in the file.
+    fn set_groups(&self, d: &mut PairsData, order: [u8; 2], file: usize) {
+        let mut n = 0;
+        let mut first_len: i32 = if self.has_pawns {
+            0
+        } else if self.has_unique_pieces {
+            3
+        } else {
+            2
+        };
+        d.group_len[0] = 1;
+        for i in 1..self.piece_count {
+            first_len -= 1;
+            if first_len > 0 || d.pieces[i] == d.pieces[i - 1] {
+                d.group_len[n] += 1;
+            } else {
+                n += 1;
+                d.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        d.group_len[n] = 0;
+
+        let both_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        let mut next = if both_pawns { 2 } else { 1 };
+        let mut free_squares = 64 - d.group_len[0] - if both_pawns { d.group_len[1] } else { 0 };
+        let mut idx = 1;
+        let mut k = 0;
+        while next < n || k == order[0] || k == order[1] {
+            if k == order[0] {
+                d.group_idx[0] = idx;
+                idx *= if self.has_pawns {
+                    ENCODING.lead_pawns_size[d.group_len[0]][file]
+                } else if self.has_unique_pieces {
+                    31332
+                } else {
+                    462
+                };
+            } else if k == order[1] {
+                d.group_idx[1] = idx;
+                idx *= ENCODING.binomial[d.group_len[1]][48 - d.group_len[0]];
+            } else {
+                d.group_idx[next] = idx;
+                idx *= ENCODING.binomial[d.group_len[next]][free_squares];
+                free_squares -= d.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        d.group_idx[n] = idx;
+    }
+}
+
+impl Layout {
+    // wdl tables can store both sides to move, dtz tables only one
+    fn subtable(&self, table: &Table, stm: usize, file: usize) -> &PairsData {
+        let side = if table.kind == Kind::Wdl { stm } else { 0 };
+        &self.subtables[side * 4 + if table.has_pawns { file } else { 0 }]
+    }
+}
+
+fn set_sizes(d: &mut PairsData, bytes: &[u8], mut pos: usize) -> Option<usize> {
+    d.flags = *bytes.get(pos)?;
+    pos += 1;
+    if d.flags & SINGLE_VALUE != 0 {
+        d.min_sym_len = *bytes.get(pos)?;
+        return Some(pos + 1);
+    }
+
+    let groups = d.group_len.iter().position(|&len| len == 0)?;
+    let table_size = d.group_idx[groups] as usize;
+    d.block_size = 1usize.checked_shl(*bytes.get(pos)? as u32)?;
+    d.span = 1usize.checked_shl(*bytes.get(pos + 1)? as u32)?;
+    d.sparse_index_size = table_size.div_ceil(d.span);
+    let padding = *bytes.get(pos + 2)? as usize;
+    d.num_blocks = read_u32(bytes, pos + 3)? as usize;
+    d.block_length_size = d.num_blocks + padding;
+    let max_sym_len = *bytes.get(pos + 7)?;
+    d.min_sym_len = *bytes.get(pos + 8)?;
+    pos += 9;
+    if d.min_sym_len == 0 || max_sym_len < d.min_sym_len || max_sym_len > 64 {
+        return None;
+    }
+
+    // Symbols of the same length are consecutive numbers, and longer symbols come before shorter ones. base64
+    // holds the lowest symbol of each length, padded out to 64 bits, so the length of the symbol at the start
+    // of a stream of bits is the first one whose base is at most those bits.
+    d.lowest_sym = pos;
+    let lengths = (max_sym_len - d.min_sym_len + 1) as usize;
+    let lowest = |len: usize| read_u16(bytes, d.lowest_sym + 2 * len).map(u64::from);
+    d.base64 = vec![0; lengths];
+    for len in (0..lengths - 1).rev() {
+        d.base64[len] = (d.base64[len + 1] + lowest(len)?).wrapping_sub(lowest(len + 1)?) / 2;
+    }
+    for (len, base) in d.base64.iter_mut().enumerate() {
+        *base = base.wrapping_shl((64 - len - d.min_sym_len as usize) as u32);
+    }
+    pos += lengths * 2;
+
+    // each symbol stands for a pair of smaller symbols, down to single values
+    let symbols = read_u16(bytes, pos)? as usize;
+    pos += 2;
+    d.btree = pos;
+    if bytes.len() < d.btree + symbols * 3 {
+        return None;
+    }
+    d.symlen = vec![0; symbols];
+    let mut visited = vec![false; symbols];
+    for symbol in 0..symbols {
+        if !visited[symbol] {
+            d.symlen[symbol] = set_symlen(d, bytes, symbol, &mut visited)?;
+        }
+    }
+    Some(pos + symbols * 3 + (symbols & 1))
+}
+
+// how many values, minus one, a symbol expands into
+fn set_symlen(d: &mut PairsData, bytes: &[u8], symbol: usize, visited: &mut [bool]) -> Option<u8> {
+    visited[symbol] = true;
+    let (left, right) = children(bytes, d.btree, symbol)?;
+    if right == 0xfff {
+        return Some(0);
+    }
+    for child in [left, right] {
+        if !*visited.get(child)? {
+            d.symlen[child] = set_symlen(d, bytes, child, visited)?;
+        }
+    }
+    Some(d.symlen[left].wrapping_add(d.symlen[right]).wrapping_add(1))
+}
+
+fn children(bytes: &[u8], btree: usize, symbol: usize) -> Option<(usize, usize)> {
+    let node = bytes.get(btree + symbol * 3..btree + symbol * 3 + 3)?;
+    let left = ((node[1] as usize & 0xf) << 8) | node[0] as usize;
+    let right = ((node[2] as usize) << 4) | (node[1] as usize >> 4);
+    Some((left, right))
+}
+
+// None when the table turns out to be truncated or corrupt
+fn decompress(bytes: &[u8], d: &PairsData, idx: u64) -> Option<i32> {
+    if d.flags & SINGLE_VALUE != 0 {
+        return Some(d.min_sym_len as i32);
+    }
+
+    // The sparse index gives the block and offset of every span-th value, from which the block holding
+    // this one is found by walking the block lengths.
+    let idx = idx as usize;
+    let entry = d.sparse_index + 6 * (idx / d.span);
+    let mut block = read_u32(bytes, entry)? as usize;
+    let mut offset = read_u16(bytes, entry + 4)? as i64;
+    offset += (idx % d.span) as i64 - (d.span / 2) as i64;
+    let block_length = |block: usize| read_u16(bytes, d.block_length + 2 * block).map(i64::from);
+    while offset < 0 {
+        block = block.checked_sub(1)?;
+        offset += block_length(block)? + 1;
+    }
+    while offset > block_length(block)? {
+        offset -= block_length(block)? + 1;
+        block += 1;
+    }
+
+    // then the block's symbols are read until the one covering the offset
+    let mut ptr = d.data + block.checked_mul(d.block_size)?;
+    let mut buf64 = u64::from_be_bytes(bytes.get(ptr..ptr + 8)?.try_into().ok()?);
+    ptr += 8;
+    let mut buf64_size: usize = 64;
+    let min_sym_len = d.min_sym_len as usize;
+    let mut symbol;
+    loop {
+        let mut len = 0;
+        while buf64 < *d.base64.get(len)? {
+            len += 1;
+        }
+        symbol = ((buf64 - d.base64[len]) >> (64 - len - min_sym_len)) as usize;
+        symbol += read_u16(bytes, d.lowest_sym + 2 * len)? as usize;
+        let symlen = *d.symlen.get(symbol)? as i64;
+        if offset < symlen + 1 {
+            break;
+        }
+        offset -= symlen + 1;
+        len += min_sym_len;
+        buf64 = buf64.checked_shl(len as u32)?;
+        buf64_size = buf64_size.checked_sub(len)?;
+        if buf64_size <= 32 {
+            buf64_size += 32;
+            let next = u32::from_be_bytes(bytes.get(ptr..ptr + 4)?.try_into().ok()?);
+            buf64 |= (next as u64) << (64 - buf64_size);
+            ptr += 4;
+        }
+    }
+
+    // and expanded down to the single value at the offset
+    while *d.symlen.get(symbol)? != 0 {
+        let (left, right) = children(bytes, d.btree, symbol)?;
+        let left_len = *d.symlen.get(left)? as i64;
+        if offset < left_len + 1 {
+            symbol = left;
+        } else {
+            offset -= left_len + 1;
+            symbol = right;
+        }
+    }
+    Some(children(bytes, d.btree, symbol)?.0 as i32)
+}
+
+// dtz tables store values in moves or plies, and may store them through a map to compress better
+fn map_dtz(bytes: &[u8], d: &PairsData, value: i32, wdl: i32) -> i32 {
+    const WDL_MAP: [usize; 5] = [1, 3, 0, 2, 0];
+    let mut value = value;
+    if d.flags & MAPPED != 0 {
+        let map = d.map_idx[WDL_MAP[(wdl + 2) as usize]];
+        value = if d.flags & WIDE != 0 {
+            read_u16(bytes, map + 2 * value as usize).unwrap_or(0) as i32
+        } else {
+            *bytes.get(map + value as usize).unwrap_or(&0) as i32
+        };
+    }
+    let in_moves = (wdl == 2 && d.flags & WIN_PLIES == 0)
+        || (wdl == -2 && d.flags & LOSS_PLIES == 0)
+        || wdl.abs() == 1;
+    if in_moves {
+        value *= 2;
+    }
+    value + 1
+}
+
+fn read_u16(bytes: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        bytes.get(pos..pos + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(bytes: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(pos..pos + 4)?.try_into().ok()?,
+    ))
+}
+
+// pieces are coded 1 to 6 for white's pawn to king, with 8 added for black's
+fn tb_piece(board: &Board, square: Square) -> u8 {
+    let color = if board.is_color(square, Color::W) {
+        0
+    } else {
+        8
+    };
+    board.get_piece(square) as u8 + 1 + color
+}
+
+fn edge_distance(file: usize) -> usize {
+    file.min(7 - file)
+}
+
+// positive above the a1-h8 diagonal, negative below it
+fn off_diagonal(square: usize) -> i32 {
+    (square / 8) as i32 - (square % 8) as i32
+}
+
+struct Encoding {
+    map_b1h1h7: [usize; 64],
+    map_a1d1d4: [usize; 64],
+    map_kk: [[u64; 64]; 10],
+    binomial: [[u64; 64]; MAX_PIECES],
+    map_pawns: [usize; 64],
+    lead_pawn_idx: [[u64; 64]; 6],
+    lead_pawns_size: [[u64; 4]; 6],
+}
+
+#[dynamic]
+static ENCODING: Encoding = Encoding::new();
+
+impl Encoding {
+    fn new() -> Self {
+        let mut encoding = Encoding {
+            map_b1h1h7: [0; 64],
+            map_a1d1d4: [0; 64],
+            map_kk: [[0; 64]; 10],
+            binomial: [[0; 64]; MAX_PIECES],
+            map_pawns: [0; 64],
+            lead_pawn_idx: [[0; 64]; 6],
+            lead_pawns_size: [[0; 4]; 6],
+        };
+
+        // squares below the a1-h8 diagonal
+        let mut code = 0;
+        for square in 0..64 {
+            if off_diagonal(square) < 0 {
+                encoding.map_b1h1h7[square] = code;
+                code += 1;
+            }
+        }
+
+        // the a1-d1-d4 triangle, with the diagonal squares last
+        let triangle = (0..64).filter(|&square: &usize| square % 8 <= 3 && square / 8 <= 3);
+        let (below, diagonal): (Vec<usize>, Vec<usize>) = triangle
+            .filter(|&square| off_diagonal(square) <= 0)
+            .partition(|&square| off_diagonal(square) < 0);
+        for (code, &square) in below.iter().chain(diagonal.iter()).enumerate() {
+            encoding.map_a1d1d4[square] = code;
+        }
+
+        // The 462 ways to place two kings with the first in the triangle. If the first is on the diagonal,
+        // the second can't be above it. Both kings on the diagonal come last.
+        let mut code = 0;
+        let mut both_on_diagonal = Vec::new();
+        for idx in 0..10 {
+            let first = (0..64).find(|&square| {
+                encoding.map_a1d1d4[square] == idx
+                    && square % 8 <= 3
+                    && square / 8 <= 3
+                    && off_diagonal(square) <= 0
+            });
+            let Some(first) = first else {
+                continue;
+            };
+            for second in 0..64 {
+                let touching =
+                    atks::king_attacks(first as Square) | bitboard::new_bb(first as Square);
+                if touching & bitboard::new_bb(second as Square) != 0
+                    || (off_diagonal(first) == 0 && off_diagonal(second) > 0)
+                {
+                    continue;
+                }
+                if off_diagonal(first) == 0 && off_diagonal(second) == 0 {
+                    both_on_diagonal.push((idx, second));
+                } else {
+                    encoding.map_kk[idx][second] = code;
+                    code += 1;
+                }
+            }
+        }
+        for (idx, second) in both_on_diagonal {
+            encoding.map_kk[idx][second] = code;
+            code += 1;
+        }
+
+        encoding.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..MAX_PIECES.min(n + 1) {
+                let with = if k > 0 {
+                    encoding.binomial[k - 1][n - 1]
+                } else {
+                    0
+                };
+                let without = if k < n {
+                    encoding.binomial[k][n - 1]
+                } else {
+                    0
+                };
+                encoding.binomial[k][n] = with + without;
+            }
+        }
+
+        // Pawns on a2 to h7, numbered so the leading pawn has the highest number: closest to the edge, then
+        // furthest back. Each leading pawn square leaves that many squares for the other leading pawns.
+        let mut available = 47;
+        for lead_pawns in 1..=5 {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..7 {
+                    let square = square::new_sq(rank, file as u8) as usize;
+                    if lead_pawns == 1 {
+                        encoding.map_pawns[square] = available;
+                        encoding.map_pawns[square ^ 7] = available - 1;
+                        available = available.saturating_sub(2);
+                    }
+                    encoding.lead_pawn_idx[lead_pawns][square] = idx;
+                    idx += encoding.binomial[lead_pawns - 1][encoding.map_pawns[square]];
+                }
+                encoding.lead_pawns_size[lead_pawns][file] = idx;
+            }
+        }
+        encoding
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, path::PathBuf};
+
+    use super::{decompress, Kind, PairsData, Table};
+
+    // the eight symmetries of the board
+    fn transform(square: usize, symmetry: usize) -> usize {
+        let mut square = square;
+        if symmetry & 1 != 0 {
+            square ^= 7;
+        }
+        if symmetry & 2 != 0 {
+            square ^= 56;
+        }
+        if symmetry & 4 != 0 {
+            square = ((square >> 3) | (square << 3)) & 63;
+        }
+        square
+    }
+
+    // the same steps probe takes before encoding
+    fn encode(table: &Table, squares: [usize; 3]) -> u64 {
+        let mut squares = squares;
+        if squares[0] % 8 > 3 {
+            for square in squares.iter_mut() {
+                *square ^= 7;
+            }
+        }
+        table.encode_pieces(&mut squares, 3)
+    }
+
+    // Three unique pieces are encoded together into 31332 values, which the group sizes are built on. Every
+    // placement has to land inside them, symmetric placements on the same index and the rest on different ones.
+    #[test]
+    fn three_unique_pieces() {
+        let table = Table::new(Kind::Wdl, PathBuf::new(), "KQvK").unwrap();
+        let mut placements = HashMap::new();
+        for s0 in 0..64 {
+            for s1 in (0..64).filter(|&s1| s1 != s0) {
+                for s2 in (0..64).filter(|&s2| s2 != s0 && s2 != s1) {
+                    let idx = encode(&table, [s0, s1, s2]);
+                    assert!(idx < 31332, "{s0} {s1} {s2} encoded to {idx}");
+
+                    let images = (0..8)
+                        .map(|symmetry| [s0, s1, s2].map(|square| transform(square, symmetry)));
+                    let canonical = images.clone().min().unwrap();
+                    for image in images {
+                        assert_eq!(encode(&table, image), idx, "{image:?}");
+                    }
+                    let previous = placements.entry(idx).or_insert(canonical);
+                    assert_eq!(*previous, canonical, "{idx} is used twice");
+                }
+            }
+        }
+    }
+
+    // A block of 64 values stored one bit each: symbols 0 and 1 are single values, and both are one bit long.
+    fn one_bit_table(bits: u64) -> (Vec<u8>, PairsData) {
+        let mut bytes = Vec::new();
+        bytes.extend(0u32.to_le_bytes()); // sparse index: block 0 at offset 32, which is span / 2
+        bytes.extend(32u16.to_le_bytes());
+        bytes.extend(63u16.to_le_bytes()); // block length, minus one
+        bytes.extend(0u16.to_le_bytes()); // lowest symbol
+        bytes.extend([0x00, 0xf0, 0xff, 0x01, 0xf0, 0xff]); // btree
+        bytes.extend(bits.to_be_bytes());
+        bytes.extend([0; 4]);
+        let d = PairsData {
+            min_sym_len: 1,
+            block_size: 64,
+            span: 64,
+            sparse_index: 0,
+            block_length: 6,
+            lowest_sym: 8,
+            btree: 10,
+            data: 16,
+            base64: vec![0],
+            symlen: vec![0, 0],
+            ..PairsData::default()
+        };
+        (bytes, d)
+    }
+
+    #[test]
+    fn decompress_values() {
+        let bits = 0xa5c3_0f00_ff12_3456;
+        let (bytes, d) = one_bit_table(bits);
+        for idx in 0..64 {
+            assert_eq!(
+                decompress(&bytes, &d, idx),
+                Some((bits >> (63 - idx) & 1) as i32)
+            );
+        }
+    }
+
+    // a broken table gives no value instead of panicking
+    #[test]
+    fn decompress_corrupt() {
+        let bits = 0xa5c3_0f00_ff12_3456;
+        let (bytes, d) = one_bit_table(bits);
+        for len in 0..bytes.len() {
+            for idx in 0..64 {
+                let value = decompress(&bytes[..len], &d, idx);
+                assert!(value.is_none_or(|value| value == (bits >> (63 - idx) & 1) as i32));
+            }
+        }
+        // an index past the sparse index
+        assert_eq!(decompress(&bytes, &d, 64), None);
+
+        // the offset goes back before the first block
+        let mut before_first = bytes.clone();
+        before_first[4..6].copy_from_slice(&0u16.to_le_bytes());
+        assert_eq!(decompress(&before_first, &d, 0), None);
+
+        // symbols that aren't in the btree
+        let mut unknown_symbol = bytes.clone();
+        unknown_symbol[8..10].copy_from_slice(&7u16.to_le_bytes());
+        assert_eq!(decompress(&unknown_symbol, &d, 0), None);
+        let mut bad_child = bytes.clone();
+        bad_child[13..16].copy_from_slice(&[0x05, 0x00, 0x00]);
+        let d_pair = PairsData {
+            symlen: vec![0, 1],
+            ..d.clone()
+        };
+        assert_eq!(decompress(&bad_child, &d_pair, 0), None);
+    }
+}
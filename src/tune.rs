@@ -0,0 +1,313 @@
+// Texel tuning. Fits the hand-crafted evaluation to the results of the games a set of quiet positions came
+// from, by gradient descent on the squared error between each result and a sigmoid of the evaluation.
+//
+// usage: ShenYu tune <positions> [output] [epochs]
+// Each line of the positions file is a fen (the move counters may be left out) followed somewhere by the
+// result from white's point of view, either as 1-0, 0-1 and 1/2-1/2 or as [1.0], [0.0] and [0.5].
+// The tuned parameters are written out as a parameter file the engine can load with the EvalParamFile option,
+// or as rust source ready to be pasted over the old constants if the output file ends in .rs.
+
+use std::{
+    fmt::Write as _,
+    fs::{self, File},
+    io::{BufRead, BufReader},
+    thread,
+};
+
+use anyhow::{bail, Context, Result};
+
+use shenyu::{
+    eval::{
+        params::{EvalParams, NUM_PARAMS, PIECE_NAMES, TERMS},
+        psqt::TOTAL_PHASE,
+        score::Score,
+    },
+    movegen::board::Board,
+};
+
+const DEFAULT_OUTPUT: &str = "tuned.txt";
+const DEFAULT_EPOCHS: usize = 1000;
+const REPORT_INTERVAL: usize = 50;
+
+// adam
+const LEARNING_RATE: f64 = 1.0;
+const BETA1: f64 = 0.9;
+const BETA2: f64 = 0.999;
+const EPSILON: f64 = 1e-8;
+
+// a middlegame and an endgame value for every parameter
+type Params = Vec<[f64; 2]>;
+
+struct Position {
+    coefficients: Vec<(usize, f64)>,
+    mg_phase: f64, // how much of the evaluation comes from the middlegame values, between 0 and 1
+    result: f64,
+}
+
+pub fn run(mut args: impl Iterator<Item = String>) -> Result<()> {
+    let input = args
+        .next()
+        .context("usage: tune <positions> [output] [epochs]")?;
+    let output = args.next().unwrap_or_else(|| DEFAULT_OUTPUT.to_string());
+    let epochs = match args.next() {
+        Some(epochs) => epochs.parse().context("Invalid epoch count")?,
+        None => DEFAULT_EPOCHS,
+    };
+
+    let positions = load_positions(&input)?;
+    if positions.is_empty() {
+        bail!("No positions in {input}")
+    }
+    println!("loaded {} positions", positions.len());
+
+    let mut params: Params = EvalParams::default()
+        .values
+        .iter()
+        .map(|score| [score.mg as f64, score.eg as f64])
+        .collect();
+    let k = fit_scaling(&positions, &params);
+    println!("K = {k:.4}, error {:.6}", error(&positions, &params, k));
+
+    let mut optimizer = Adam::new();
+    for epoch in 1..=epochs {
+        let gradient = gradient(&positions, &params, k);
+        optimizer.step(&mut params, &gradient);
+
+        if epoch % REPORT_INTERVAL == 0 || epoch == epochs {
+            println!("epoch {epoch}, error {:.6}", error(&positions, &params, k));
+            // saved as we go, so a long run can be cut short
+            let tuned = rounded(&params);
+            let contents = if output.ends_with(".rs") {
+                to_source(&tuned)
+            } else {
+                tuned.to_string()
+            };
+            fs::write(&output, contents).with_context(|| format!("Could not write {output}"))?;
+        }
+    }
+    println!("tuned parameters written to {output}");
+    Ok(())
+}
+
+fn load_positions(path: &str) -> Result<Vec<Position>> {
+    let file = File::open(path).with_context(|| format!("Could not open {path}"))?;
+    let mut board = Board::new();
+    let mut positions = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let position = parse_position(&mut board, &line)
+            .with_context(|| format!("{path}, line {}", number + 1))?;
+        positions.push(position);
+    }
+    Ok(positions)
+}
+
+fn parse_position(board: &mut Board, line: &str) -> Result<Position> {
+    let fields = line.split_whitespace().collect::<Vec<&str>>();
+    if fields.len() < 4 {
+        bail!("Invalid fen")
+    }
+    // epd style lines stop after the en passant square
+    let counters = match fields.get(4..6) {
+        Some(counters) if counters.iter().all(|field| field.parse::<u32>().is_ok()) => {
+            counters.join(" ")
+        }
+        _ => String::from("0 1"),
+    };
+    board.parse_fen(&format!("{} {counters}", fields[..4].join(" ")))?;
+    let result = fields[4..]
+        .iter()
+        .find_map(|field| parse_result(field))
+        .context("No game result")?;
+
+    let (_, trace) = board.trace_eval();
+    let coefficients = (0..NUM_PARAMS)
+        .map(|param| (param, trace.coefficient(param)))
+        .filter(|&(_, coefficient)| coefficient != 0)
+        .map(|(param, coefficient)| (param, coefficient as f64))
+        .collect();
+    Ok(Position {
+        coefficients,
+        mg_phase: trace.phase.min(TOTAL_PHASE) as f64 / TOTAL_PHASE as f64,
+        result,
+    })
+}
+
+fn parse_result(field: &str) -> Option<f64> {
+    match field.trim_matches(|c| matches!(c, '"' | ';' | '[' | ']')) {
+        "1-0" => Some(1.0),
+        "0-1" => Some(0.0),
+        "1/2-1/2" => Some(0.5),
+        number if field.starts_with('[') => number
+            .parse()
+            .ok()
+            .filter(|result| (0.0..=1.0).contains(result)),
+        _ => None,
+    }
+}
+
+fn evaluate(position: &Position, params: &Params) -> f64 {
+    let (mut mg, mut eg) = (0.0, 0.0);
+    for &(param, coefficient) in position.coefficients.iter() {
+        mg += params[param][0] * coefficient;
+        eg += params[param][1] * coefficient;
+    }
+    mg * position.mg_phase + eg * (1.0 - position.mg_phase)
+}
+
+// expected score from white's point of view
+fn sigmoid(eval: f64, k: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-k * eval / 400.0))
+}
+
+fn error(positions: &[Position], params: &Params, k: f64) -> f64 {
+    let total = for_each_chunk(positions, |chunk| {
+        chunk
+            .iter()
+            .map(|position| (position.result - sigmoid(evaluate(position, params), k)).powi(2))
+            .sum::<f64>()
+    });
+    total.iter().sum::<f64>() / positions.len() as f64
+}
+
+// The scaling constant maps evaluations to expected results. It is fitted once to the starting parameters
+// and then held fixed, so the tuned values stay in centipawns.
+fn fit_scaling(positions: &[Position], params: &Params) -> f64 {
+    let (mut low, mut high) = (0.0, 5.0);
+    for _ in 0..50 {
+        let third = (high - low) / 3.0;
+        if error(positions, params, low + third) < error(positions, params, high - third) {
+            high -= third;
+        } else {
+            low += third;
+        }
+    }
+    (low + high) / 2.0
+}
+
+// The constant factors of the derivative are left out, since adam doesn't care about the gradient's scale.
+fn gradient(positions: &[Position], params: &Params, k: f64) -> Params {
+    let partials = for_each_chunk(positions, |chunk| {
+        let mut gradient = vec![[0.0; 2]; NUM_PARAMS];
+        for position in chunk {
+            let expected = sigmoid(evaluate(position, params), k);
+            let slope = (expected - position.result) * expected * (1.0 - expected);
+            for &(param, coefficient) in position.coefficients.iter() {
+                gradient[param][0] += slope * coefficient * position.mg_phase;
+                gradient[param][1] += slope * coefficient * (1.0 - position.mg_phase);
+            }
+        }
+        gradient
+    });
+
+    let mut gradient = vec![[0.0; 2]; NUM_PARAMS];
+    for partial in partials {
+        for (total, part) in gradient.iter_mut().zip(partial) {
+            total[0] += part[0];
+            total[1] += part[1];
+        }
+    }
+    gradient
+}
+
+// splits the positions up between as many threads as there are cores
+fn for_each_chunk<T: Send>(
+    positions: &[Position],
+    work: impl Fn(&[Position]) -> T + Sync,
+) -> Vec<T> {
+    let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+    let chunk_size = positions.len().div_ceil(threads).max(1);
+    thread::scope(|scope| {
+        let handles = positions
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(|| work(chunk)))
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    })
+}
+
+struct Adam {
+    momentum: Params,
+    velocity: Params,
+    steps: i32,
+}
+
+impl Adam {
+    fn new() -> Self {
+        Self {
+            momentum: vec![[0.0; 2]; NUM_PARAMS],
+            velocity: vec![[0.0; 2]; NUM_PARAMS],
+            steps: 0,
+        }
+    }
+
+    fn step(&mut self, params: &mut Params, gradient: &Params) {
+        self.steps += 1;
+        let momentum_correction = 1.0 - BETA1.powi(self.steps);
+        let velocity_correction = 1.0 - BETA2.powi(self.steps);
+        for param in 0..NUM_PARAMS {
+            for phase in 0..2 {
+                let gradient = gradient[param][phase];
+                let momentum = &mut self.momentum[param][phase];
+                let velocity = &mut self.velocity[param][phase];
+                *momentum = BETA1 * *momentum + (1.0 - BETA1) * gradient;
+                *velocity = BETA2 * *velocity + (1.0 - BETA2) * gradient * gradient;
+                params[param][phase] -= LEARNING_RATE * (*momentum / momentum_correction)
+                    / ((*velocity / velocity_correction).sqrt() + EPSILON);
+            }
+        }
+    }
+}
+
+fn rounded(params: &Params) -> EvalParams {
+    let value = |value: f64| value.round() as i16;
+    EvalParams {
+        values: params
+            .iter()
+            .map(|&[mg, eg]| Score::new(value(mg), value(eg)))
+            .collect(),
+        ..EvalParams::default()
+    }
+}
+
+// rust source in the same layout as the constants in src/eval
+fn to_source(params: &EvalParams) -> String {
+    let score = |param: usize| format!("Score::new({}, {})", params[param].mg, params[param].eg);
+
+    let mut source = String::new();
+    for (phase, phase_name) in ["MG", "EG"].iter().enumerate() {
+        for (piece, piece_name) in PIECE_NAMES.iter().enumerate() {
+            writeln!(source, "#[rustfmt::skip]").unwrap();
+            writeln!(source, "const {phase_name}_{piece_name}: [i16; 64] = [").unwrap();
+            for rank in 0..8 {
+                let row = (0..8)
+                    .map(|file| {
+                        let score = params.psqt(piece, rank * 8 + file);
+                        if phase == 0 { score.mg } else { score.eg }.to_string()
+                    })
+                    .collect::<Vec<String>>();
+                writeln!(source, "    {},", row.join(", ")).unwrap();
+            }
+            writeln!(source, "];\n").unwrap();
+        }
+    }
+
+    for (name, start, len) in TERMS {
+        if len == 1 {
+            writeln!(source, "const {name}: Score = {};", score(start)).unwrap();
+        } else {
+            writeln!(source, "const {name}: [Score; {len}] = [").unwrap();
+            for param in start..start + len {
+                writeln!(source, "    {},", score(param)).unwrap();
+            }
+            writeln!(source, "];").unwrap();
+        }
+    }
+    source
+}
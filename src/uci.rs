@@ -1,41 +1,31 @@
 use std::{
-    cmp, io,
+    io,
     sync::{Arc, Mutex},
     thread,
 };
 
-use crate::{
-    movegen::{board::Board, genmoves::GenType, movelist::MoveList, types::Color},
-    search::{searchcontrol::Searcher, timer::Timer},
+use shenyu::{
+    eval::pawns::PawnTable,
+    movegen::{board::Board, perft, types::Color},
+    search::{
+        searchcontrol::{
+            Bound, SearchCommand, SearchInfo, SearchResult, Searcher, MAX_MULTIPV, MAX_THREADS,
+        },
+        timer::{
+            SearchLimits, DEFAULT_MIN_THINK_TIME, DEFAULT_MOVE_OVERHEAD, MAX_MIN_THINK_TIME,
+            MAX_MOVE_OVERHEAD,
+        },
+    },
 };
 
 pub const VERSION: &str = "2.0.1";
 
-impl Board {
-    pub fn parse_moves<'a, T>(&mut self, actions: T) -> Option<()>
-    where
-        T: Iterator<Item = &'a str>,
-    {
-        for movestring in actions {
-            self.parse_move(movestring)?;
-        }
-        Some(())
-    }
-
-    fn parse_move(&mut self, movestring: &str) -> Option<()> {
-        let mut list = MoveList::new();
-        self.genmoves::<{ GenType::ALL }>(&mut list);
-        let action = list
-            .iter()
-            .find(|&action| action.to_string() == movestring)?;
-        self.make_move(**action);
-        Some(())
-    }
-}
-
 pub fn gameloop() {
-    let (tx, rx) = crossbeam::channel::unbounded::<bool>();
-    let searchdata_ptr = Arc::new(Mutex::new(Searcher::new(rx)));
+    let (tx, rx) = crossbeam::channel::unbounded::<SearchCommand>();
+    // kept around to throw away commands that arrived after the last search was already over
+    let stale_rx = rx.clone();
+    let searcher = Searcher::new(rx);
+    let searchdata_ptr = Arc::new(Mutex::new(searcher));
     let mut cmd = String::new();
     loop {
         cmd.clear();
@@ -53,7 +43,13 @@ pub fn gameloop() {
                 continue;
             }
             "stop" => {
-                tx.send(true).expect("Error: Search Thread Disconnected");
+                tx.send(SearchCommand::Stop)
+                    .expect("Error: Search Thread Disconnected");
+                continue;
+            }
+            "ponderhit" => {
+                tx.send(SearchCommand::PonderHit)
+                    .expect("Error: Search Thread Disconnected");
                 continue;
             }
             "quit" => return,
@@ -65,16 +61,21 @@ pub fn gameloop() {
 
         match command_type {
             "setoption" => set_option(&mut searchdata, split),
+            "eval" => print_eval(searchdata.get_board()),
             "ucinewgame" => searchdata.reset(),
             "position" => parse_position(&mut searchdata, split),
             "go" => {
-                if parse_go(&mut searchdata, split) {
+                let Some(limits) = parse_go(&mut searchdata, split) else {
                     continue;
-                }
+                };
+                while stale_rx.try_recv().is_ok() {}
+                // moves are written according to UCI_Chess960 as it was when the search started
+                let chess960 = searchdata.get_board().is_chess960();
+                searchdata.set_reporter(move |info| print_info(info, chess960));
                 drop(searchdata);
                 thread::spawn(move || {
                     let mut searcher = searchdata_clone.lock().unwrap();
-                    searcher.search();
+                    print_bestmove(&searcher.search(&limits), chess960);
                 });
             }
             _ => continue,
@@ -82,11 +83,89 @@ pub fn gameloop() {
     }
 }
 
+fn print_info(info: &SearchInfo, chess960: bool) {
+    let (scoretype, reported_score) = match info.mate {
+        Some(moves) => ("mate", moves),
+        None => ("cp", info.score),
+    };
+    let bound = match info.bound {
+        Bound::Exact => "",
+        Bound::Lower => " lowerbound",
+        Bound::Upper => " upperbound",
+    };
+    let multipv = if info.lines > 1 {
+        format!(" multipv {}", info.line)
+    } else {
+        String::new()
+    };
+    let nps = (info.nodes * 1000).checked_div(info.time).unwrap_or(0);
+    let pv: String = info
+        .pv
+        .iter()
+        .map(|action| format!(" {}", action.to_uci(chess960)))
+        .collect();
+
+    println!(
+        "info depth {}{} score {} {}{} nodes {} nps {} tbhits {} hashfull {} time {} pv{}",
+        info.depth,
+        multipv,
+        scoretype,
+        reported_score,
+        bound,
+        info.nodes,
+        nps,
+        info.tb_hits,
+        info.hashfull,
+        info.time,
+        pv
+    );
+}
+
+fn print_bestmove(result: &SearchResult, chess960: bool) {
+    let best_move = result.best_move.map(|action| action.to_uci(chess960));
+    let ponder_move = result.ponder_move.map(|action| action.to_uci(chess960));
+    match (best_move, ponder_move) {
+        (None, _) => println!("bestmove 0000"),
+        (Some(best_move), Some(ponder_move)) => {
+            println!("bestmove {best_move} ponder {ponder_move}")
+        }
+        (Some(best_move), None) => println!("bestmove {best_move}"),
+    }
+}
+
+fn print_eval(board: &Board) {
+    print!("{}", board.eval_breakdown());
+    // the search goes by the network instead, if one is loaded
+    let mut eval = board.evaluate(&mut PawnTable::new());
+    if board.active_color() == Color::B {
+        eval = -eval;
+    }
+    let evaluator = if board.network().is_some() {
+        "nnue"
+    } else {
+        "hand-crafted"
+    };
+    println!("final evaluation {eval} ({evaluator}, white's point of view)");
+}
+
 fn identify() {
     println!("id name ShenYu {VERSION}");
     println!("id author Aaron Li");
     println!("option name Hash type spin default 64 min 0 max 65536");
     println!("option name Clear Hash type button");
+    println!("option name Threads type spin default 1 min 1 max {MAX_THREADS}");
+    println!("option name MultiPV type spin default 1 min 1 max {MAX_MULTIPV}");
+    println!("option name Ponder type check default false");
+    println!(
+        "option name Move Overhead type spin default {DEFAULT_MOVE_OVERHEAD} min 0 max {MAX_MOVE_OVERHEAD}"
+    );
+    println!(
+        "option name Minimum Thinking Time type spin default {DEFAULT_MIN_THINK_TIME} min 0 max {MAX_MIN_THINK_TIME}"
+    );
+    println!("option name EvalFile type string default <empty>");
+    println!("option name EvalParamFile type string default <empty>");
+    println!("option name SyzygyPath type string default <empty>");
+    println!("option name UCI_Chess960 type check default false");
     println!("uciok");
 }
 
@@ -99,19 +178,70 @@ where
         return;
     }
 
-    let option_type = string_iter.next().unwrap_or_default();
-    match option_type {
-        "Hash" | "hash" => {
-            let mut next = string_iter.next().unwrap();
-            if next != "value" {
-                return;
-            }
-            next = string_iter.next().unwrap();
-            if let Ok(size) = str::parse::<usize>(next) {
+    // option names can have spaces in them, so everything up until "value" is part of the name
+    let mut name_parts = Vec::new();
+    for part in string_iter.by_ref() {
+        if part == "value" {
+            break;
+        }
+        name_parts.push(part);
+    }
+    let option_name = name_parts.join(" ").to_ascii_lowercase();
+    let value = string_iter.collect::<Vec<_>>().join(" ");
+
+    match option_name.as_str() {
+        "hash" => {
+            if let Ok(size) = str::parse::<usize>(&value) {
                 searchdata.hash_resize(size);
             }
         }
-        "Clear Hash" | "clear hash" => searchdata.clear_hash(),
+        "clear hash" => searchdata.clear_hash(),
+        "threads" => {
+            if let Ok(threads) = str::parse::<usize>(&value) {
+                searchdata.set_threads(threads);
+            }
+        }
+        // pondering is driven entirely by the gui through go ponder and ponderhit
+        "ponder" => (),
+        "multipv" => {
+            if let Ok(multipv) = str::parse::<usize>(&value) {
+                searchdata.set_multipv(multipv);
+            }
+        }
+        "move overhead" => {
+            if let Ok(overhead) = str::parse::<u64>(&value) {
+                searchdata.set_move_overhead(overhead);
+            }
+        }
+        "minimum thinking time" => {
+            if let Ok(min_time) = str::parse::<u64>(&value) {
+                searchdata.set_min_think_time(min_time);
+            }
+        }
+        "evalfile" => {
+            if let Err(error) = searchdata.set_eval_file(&value) {
+                println!("info string {error:#}, using psqt evaluation");
+                searchdata.set_eval_file("").unwrap();
+            }
+        }
+        "evalparamfile" => {
+            if let Err(error) = searchdata.set_param_file(&value) {
+                println!("info string {error:#}, using the default parameters");
+                searchdata.set_param_file("").unwrap();
+            }
+        }
+        "syzygypath" => match searchdata.set_syzygy_path(&value) {
+            Ok(0) => (),
+            Ok(count) => println!("info string found {count} tablebases"),
+            Err(error) => {
+                println!("info string {error:#}, tablebases disabled");
+                searchdata.set_syzygy_path("").unwrap();
+            }
+        },
+        // positions are read the same way either way, this only changes how castling is written
+        "uci_chess960" => searchdata
+            .get_board()
+            .set_chess960(value.eq_ignore_ascii_case("true")),
         _ => (),
     }
 }
@@ -148,11 +278,10 @@ where
                     fen_string += value;
                     fen_string += " "
                 }
-                Type::Moves => {
-                    if board.parse_move(value).is_none() {
-                        return;
-                    }
-                }
+                Type::Moves => match board.parse_move(value) {
+                    Some(action) => board.make_move(action),
+                    None => return,
+                },
                 Type::None => return,
             },
         }
@@ -165,12 +294,12 @@ where
     }
 }
 
-fn parse_go<'a, T>(searchdata: &mut Searcher, string_iter: T) -> bool
+// the limits to search with, or None if the command was handled already
+fn parse_go<'a, T>(searchdata: &mut Searcher, string_iter: T) -> Option<SearchLimits>
 where
     T: Iterator<Item = &'a str>,
 {
     enum Type {
-        Ponder,
         WTime,
         BTime,
         WInc,
@@ -184,20 +313,13 @@ where
         Perft,
     }
 
-    let mut wtime = u64::MAX;
-    let mut winc = u64::MAX;
-    let mut btime = u64::MAX;
-    let mut binc = u64::MAX;
-    let mut max_depth = u8::MAX;
-    let mut max_nodes = u64::MAX;
-
-    let mut movetime = u64::MAX;
+    let mut limits = SearchLimits::default();
     let mut curr_type = Type::Infinite;
 
     for part in string_iter {
         match part {
             "go" => continue,
-            "ponder" => curr_type = Type::Ponder,
+            "ponder" => limits.ponder = true,
             "wtime" => curr_type = Type::WTime,
             "btime" => curr_type = Type::BTime,
             "winc" => curr_type = Type::WInc,
@@ -210,74 +332,62 @@ where
             "nodes" => curr_type = Type::Nodes,
             "perft" => curr_type = Type::Perft,
             _ => match curr_type {
-                Type::Ponder => {
-                    curr_type = Type::Infinite;
-                }
                 Type::WTime => {
                     let num = str::parse::<u64>(part).unwrap_or(0);
-                    wtime = num;
+                    limits.wtime = Some(num);
 
                     curr_type = Type::Infinite;
                 }
                 Type::BTime => {
                     let num = str::parse::<u64>(part).unwrap_or(0);
-                    btime = num;
+                    limits.btime = Some(num);
 
                     curr_type = Type::Infinite;
                 }
                 Type::WInc => {
                     let num = str::parse::<u64>(part).unwrap_or(0);
-                    winc = num;
+                    limits.winc = num;
 
                     curr_type = Type::Infinite;
                 }
                 Type::BInc => {
                     let num = str::parse::<u64>(part).unwrap_or(0);
-                    binc = num;
+                    limits.binc = num;
 
                     curr_type = Type::Infinite;
                 }
                 Type::MovesToGo => {
+                    limits.movestogo = str::parse::<u64>(part).ok().filter(|&moves| moves > 0);
+
                     curr_type = Type::Infinite;
                 }
                 Type::Depth => {
                     let num = str::parse::<u8>(part).unwrap();
-                    max_depth = num;
+                    limits.depth = Some(num);
                 }
                 Type::Mate => {
+                    limits.mate = str::parse::<i16>(part).ok().filter(|&moves| moves > 0);
+
                     curr_type = Type::Infinite;
                 }
                 Type::MoveTime => {
                     let num = str::parse::<u64>(part).unwrap_or(0);
-                    movetime = num;
+                    limits.movetime = Some(num);
                 }
                 Type::Nodes => {
                     let num = str::parse::<u64>(part).unwrap_or(0);
-                    max_nodes = num;
+                    limits.nodes = Some(num);
                 }
                 Type::Infinite => continue,
                 Type::Perft => {
                     let depth = str::parse::<u8>(part).unwrap_or(0);
-                    searchdata.get_board().divide_perft(depth);
-                    return true;
+                    let board = searchdata.get_board();
+                    perft::print_divide(&board.divide_perft(depth), board.is_chess960());
+                    return None;
                 }
             },
         }
     }
 
-    let w_best_time = cmp::min(Timer::allocate_time(wtime, winc), movetime);
-    let is_timed_w = w_best_time < 500_000;
-    if searchdata.get_board().active_color() == Color::W {
-        searchdata.timer.is_timed = is_timed_w;
-        searchdata.timer.time_alloted = w_best_time;
-    }
-    let b_best_time = cmp::min(Timer::allocate_time(btime, binc), movetime);
-    let is_timed_b = b_best_time < 500_000;
-    if searchdata.get_board().active_color() == Color::B {
-        searchdata.timer.is_timed = is_timed_b;
-        searchdata.timer.time_alloted = b_best_time;
-    }
-    searchdata.timer.max_nodes = max_nodes;
-    searchdata.timer.max_depth = max_depth;
-    false
+    Some(limits)
 }
@@ -0,0 +1,31 @@
+// Uses the engine the way another crate would. Tests are built with overflow checks, which is how a debug build
+// of a crate depending on shenyu builds it.
+
+use shenyu::{Board, GenType, MoveList, SearchLimits, Searcher};
+
+#[test]
+fn generate_moves() {
+    let mut board = Board::new();
+    board
+        .parse_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1")
+        .unwrap();
+    let mut moves = MoveList::new();
+    board.genmoves::<{ GenType::ALL }>(&mut moves);
+    assert_eq!(moves.len(), 20);
+    assert_eq!(board.perft(3), 8902);
+}
+
+#[test]
+fn search() {
+    let mut searcher = Searcher::default();
+    searcher
+        .get_board()
+        .parse_moves("e4 e5 Nf3".split(' '))
+        .unwrap();
+    let result = searcher.search(&SearchLimits {
+        depth: Some(6),
+        ..Default::default()
+    });
+    assert!(result.best_move.is_some());
+    assert_eq!(result.iterations.len(), 6);
+}